@@ -0,0 +1,387 @@
+use crate::filter::{private_answer, public_query};
+use crate::mcts::{MctsAgent, MctsConfig};
+use crate::view::PlayerView;
+use crate::GameRng;
+use crate::{
+    validate_answer, Answer, CardID, Game, Object, ObjectKind, PlayerID, PriorityAction, Query,
+};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// A player agent takes the decisions for one player of a game.
+/// It is asked to answer each query directed to that player.
+pub trait PlayerAgent {
+    /// Answer `query` on behalf of the player whose view of the game is `view`.
+    /// The card ids in the query and the answer are public ids.
+    fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer;
+
+    /// Whether the agent may answer `Answer::Undo`. Only then does the game keep
+    /// the copies of its state needed to take answers back.
+    fn may_undo(&self) -> bool {
+        false
+    }
+}
+
+/// The agents which can be chosen by name on the command line
+pub const AGENT_NAMES: [&str; 3] = ["random", "heuristic", "mcts"];
+
+/// A new agent of the kind `name`, one of `AGENT_NAMES`, seeded with `seed` if it is random.
+/// A tree search agent searches with the settings of `mcts`.
+pub fn named_agent(name: &str, seed: u64, mcts: &MctsConfig) -> Option<Box<dyn PlayerAgent>> {
+    match name {
+        "random" => Some(Box::new(RandomAgent::new(seed))),
+        "heuristic" => Some(Box::new(HeuristicAgent())),
+        "mcts" => Some(Box::new(MctsAgent::new(mcts.clone(), seed))),
+        _ => None,
+    }
+}
+
+/// An agent choosing uniformly among the valid answers
+pub struct RandomAgent {
+    rng: GameRng,
+}
+
+impl RandomAgent {
+    pub fn new(seed: u64) -> Self {
+        RandomAgent {
+            rng: GameRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl PlayerAgent for RandomAgent {
+    fn answer(&mut self, _view: &PlayerView, query: &Query) -> Answer {
+        random_answer(query, &mut self.rng)
+    }
+}
+
+/// A rule based agent which plays a reasonable but predictable game.
+/// It always makes its land drop and discards the cards with the lowest
+/// `card_score` first, which makes it a baseline opponent for testing decks.
+/// The engine has neither spells nor creatures yet, so it does not cast spells
+/// or attack either.
+pub struct HeuristicAgent();
+
+/// Number of lands on the battlefield after which more lands are not much use
+const LANDS_WANTED: i32 = 6;
+
+impl PlayerAgent for HeuristicAgent {
+    fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+        let is_land = |card_id| {
+            view.definition(card_id)
+                .is_some_and(|def| def.mechanics.is_land)
+        };
+        let lands = count_lands(view.objects.values(), view.player_id, is_land);
+        heuristic_answer(query, |card_id| card_score(is_land(card_id), lands))
+    }
+}
+
+impl HeuristicAgent {
+    /// The answer of the agent for a player of a complete game, as used in simulations.
+    /// The query and the answer use the card ids of the game.
+    pub fn answer_in_game(&mut self, game: &Game, player_id: PlayerID, query: &Query) -> Answer {
+        let view = PlayerView::from_game(game, player_id);
+        let answer = self.answer(&view, &public_query(game, query));
+        private_answer(game, &answer)
+    }
+}
+
+/// An agent giving a fixed list of answers in order, to reproduce a game or a bug.
+/// It panics with the query when its next answer is invalid or it has none left,
+/// so a script which no longer fits the game is noticed at once.
+pub struct ScriptedAgent {
+    answers: Vec<Answer>,
+    next: usize,
+}
+
+impl ScriptedAgent {
+    pub fn new(answers: Vec<Answer>) -> Self {
+        ScriptedAgent { answers, next: 0 }
+    }
+
+    /// Read the answers from a file with one answer per line, in JSON as serialized by serde
+    pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut answers = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                answers.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(ScriptedAgent::new(answers))
+    }
+}
+
+impl PlayerAgent for ScriptedAgent {
+    fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+        let answer = match self.answers.get(self.next) {
+            Some(answer) => answer.clone(),
+            None => panic!(
+                "player {} was asked {:?} after the {} answers of its script",
+                view.player_id,
+                query,
+                self.answers.len()
+            ),
+        };
+        // taking back a decision is up to the opponents, not a matter of the query
+        if answer != Answer::Undo && !validate_answer(query, &answer) {
+            panic!(
+                "player {} was asked {:?} but answer {} of its script is {:?}",
+                view.player_id, query, self.next, answer
+            );
+        }
+        self.next += 1;
+        answer
+    }
+
+    fn may_undo(&self) -> bool {
+        self.answers.contains(&Answer::Undo)
+    }
+}
+
+/// An agent passing on the answers of another agent and writing them to a file,
+/// one per line as `ScriptedAgent::read` reads them, so that the game can be played
+/// again with the same seed. Invalid answers are left out, they are asked again.
+pub struct RecordingAgent {
+    agent: Box<dyn PlayerAgent>,
+    writer: BufWriter<File>,
+}
+
+impl RecordingAgent {
+    /// Create the file at `path`, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P, agent: Box<dyn PlayerAgent>) -> std::io::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        Ok(RecordingAgent { agent, writer })
+    }
+}
+
+impl PlayerAgent for RecordingAgent {
+    fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+        let answer = self.agent.answer(view, query);
+        if answer == Answer::Undo || validate_answer(query, &answer) {
+            // written at once, so that the answers up to a crash are kept
+            let line = serde_json::to_string(&answer).expect("answers can be serialized");
+            writeln!(self.writer, "{}", line)
+                .and_then(|_| self.writer.flush())
+                .expect("can not write the recorded answers");
+        }
+        answer
+    }
+
+    fn may_undo(&self) -> bool {
+        self.agent.may_undo()
+    }
+}
+
+/// Take the best priority action or discard the cards with the lowest scores.
+/// Undoing is always allowed, the first turn is always taken and the deck is never changed.
+fn heuristic_answer(query: &Query, card_score: impl Fn(CardID) -> i32) -> Answer {
+    let answer = match query {
+        Query::PriorityAction(actions) => Answer::PriorityAction(
+            actions
+                .iter()
+                .max_by_key(|action| action_score(action))
+                .expect("malformed query")
+                .clone(),
+        ),
+        Query::Discard(cards, n) => {
+            let mut cards = cards.clone();
+            cards.sort_by_key(|card_id| card_score(*card_id));
+            cards.truncate(*n as usize);
+            Answer::Discard(cards)
+        }
+        Query::AllowUndo(_) => Answer::AllowUndo(true),
+        Query::PlayFirst => Answer::PlayFirst(true),
+        Query::Sideboard(main, _) => Answer::Sideboard(main.clone()),
+    };
+    assert!(validate_answer(query, &answer));
+    answer
+}
+
+/// Number of lands among `objects` controlled by `player_id`
+fn count_lands<'a>(
+    objects: impl Iterator<Item = &'a Object>,
+    player_id: PlayerID,
+    is_land: impl Fn(CardID) -> bool,
+) -> i32 {
+    objects
+        .filter(|object| object.controller_id == player_id)
+        .filter(|object| match object.kind {
+            ObjectKind::Card(cid) => is_land(cid),
+            _ => false,
+        })
+        .count() as i32
+}
+
+/// Preference among the priority actions, the highest scoring action is taken
+fn action_score(action: &PriorityAction) -> i32 {
+    match action {
+        PriorityAction::Pass => 0,
+        PriorityAction::PlayLand(_) => 1,
+    }
+}
+
+/// Value of keeping a card in hand for a player controlling `lands` lands,
+/// worse cards have lower scores
+fn card_score(is_land: bool, lands: i32) -> i32 {
+    if is_land {
+        // lands become less useful the more lands the player already has
+        LANDS_WANTED - lands
+    } else {
+        LANDS_WANTED / 2
+    }
+}
+
+pub fn random_answer(query: &Query, rng: &mut GameRng) -> Answer {
+    let answer = match query {
+        Query::Discard(cards, n) => Answer::Discard(
+            cards
+                .choose_multiple(rng, *n as usize)
+                .cloned()
+                .collect::<Vec<CardID>>(),
+        ),
+        Query::PriorityAction(actions) => {
+            Answer::PriorityAction(actions.choose(rng).expect("malformed query").clone())
+        }
+        Query::AllowUndo(_) => Answer::AllowUndo(rng.gen()),
+        Query::PlayFirst => Answer::PlayFirst(rng.gen()),
+        // there are far too many ways to change a deck, it is kept as it is
+        Query::Sideboard(main, _) => Answer::Sideboard(main.clone()),
+    };
+    assert!(validate_answer(query, &answer));
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{load_cards, test_cards, CardDefID, Deck};
+    use crate::scenario::{run, Scenario};
+    use crate::{duel, HandleError, Message, MessageConsumer, Step, User};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    const FOREST: CardDefID = 101;
+    const ELVES: CardDefID = 102;
+
+    #[test]
+    fn the_heuristic_agent_plays_a_land_when_it_can() {
+        let mut game = Scenario::new(Arc::new(load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[FOREST])
+            .library(0, &[FOREST; 3])
+            .library(1, &[FOREST; 3])
+            .active_player(0)
+            .step(Step::PrecombatMain)
+            .build()
+            .unwrap();
+        run(&mut game, vec![]).unwrap();
+        let view = PlayerView::from_game(&game, 0);
+        let query = view.maybe_query.clone().unwrap();
+        let land = view.players[0].hand()[0];
+        assert_eq!(
+            HeuristicAgent().answer(&view, &query),
+            Answer::PriorityAction(PriorityAction::PlayLand(land))
+        );
+    }
+
+    /// The definition of the card Leo discards from a hand of a forest and seven elves
+    /// with `lands` forests on the battlefield
+    fn discarded(lands: usize) -> CardDefID {
+        let mut game = Scenario::new(Arc::new(test_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(
+                0,
+                &[FOREST, ELVES, ELVES, ELVES, ELVES, ELVES, ELVES, ELVES],
+            )
+            .battlefield(0, &vec![FOREST; lands])
+            .library(0, &[FOREST; 3])
+            .library(1, &[FOREST; 3])
+            .active_player(0)
+            .step(Step::Cleanup)
+            .build()
+            .unwrap();
+        run(&mut game, vec![]).unwrap();
+        let view = PlayerView::from_game(&game, 0);
+        let query = view.maybe_query.clone().unwrap();
+        match HeuristicAgent().answer(&view, &query) {
+            Answer::Discard(cards) if cards.len() == 1 => view.cards[&cards[0]].def_id.unwrap(),
+            answer => panic!("unexpected answer {:?}", answer),
+        }
+    }
+
+    #[test]
+    fn the_heuristic_agent_discards_its_worst_card() {
+        // a land is worth more than a spell while few lands are in play
+        assert!(card_score(true, 0) > card_score(false, 0));
+        assert_eq!(discarded(0), ELVES);
+        // and less once there are enough of them
+        assert!(card_score(true, 4) < card_score(false, 4));
+        assert_eq!(discarded(4), FOREST);
+    }
+
+    /// Answers like a random agent and records the player of every view it is given
+    struct Seat(RandomAgent, Rc<RefCell<Vec<PlayerID>>>);
+
+    impl PlayerAgent for Seat {
+        fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+            self.1.borrow_mut().push(view.player_id);
+            self.0.answer(view, query)
+        }
+    }
+
+    /// Records the player asked by every query
+    struct Asked(Rc<RefCell<Vec<PlayerID>>>);
+
+    impl MessageConsumer for Asked {
+        fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+            if let Message::Query(player_id, _) = msg {
+                self.0.borrow_mut().push(*player_id);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn every_query_is_answered_by_the_agent_of_the_asked_player() {
+        let answered: Vec<_> = (0..2).map(|_| Rc::new(RefCell::new(Vec::new()))).collect();
+        let mut agents: Vec<Box<dyn PlayerAgent>> = (0..2)
+            .map(|pid| {
+                Box::new(Seat(RandomAgent::new(pid as u64), answered[pid].clone()))
+                    as Box<dyn PlayerAgent>
+            })
+            .collect();
+        let asked = Rc::new(RefCell::new(Vec::new()));
+        let mut consumers: Vec<Box<dyn MessageConsumer>> = vec![Box::new(Asked(asked.clone()))];
+        let user = |name: &str| User {
+            name: name.to_string(),
+        };
+        duel(
+            user("Leo"),
+            Deck(vec![(FOREST, 12)]),
+            user("Marc"),
+            Deck(vec![(FOREST, 12)]),
+            &mut agents,
+            &mut consumers,
+            Arc::new(load_cards()),
+            4,
+        )
+        .unwrap();
+
+        let asked = asked.borrow();
+        for (pid, answered) in answered.iter().enumerate() {
+            let answered = answered.borrow();
+            // each agent only sees the view of its own player
+            assert!(answered.iter().all(|id| *id == pid));
+            let queries = asked.iter().filter(|id| **id == pid).count();
+            assert!(queries > 0);
+            assert_eq!(answered.len(), queries);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! A player without a user interface joining a game on a server.
+//!
+//! Usage: bot <address> <name> [--agent random|heuristic|mcts] [--seed N]
+use bote::agent::named_agent;
+use bote::client::Client;
+use bote::mcts::MctsConfig;
+use bote::{card, User};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Number of times the bot tries to connect again after losing the connection
+const RECONNECTIONS: usize = 5;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: bot <address> <name> [--agent random|heuristic|mcts] [--seed N]");
+        std::process::exit(2);
+    }
+    let address = &args[1];
+    let name = &args[2];
+    let seed: u64 = match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => args
+            .get(i + 1)
+            .and_then(|arg| arg.parse().ok())
+            .expect("--seed must be followed by a number"),
+        None => rand::random(),
+    };
+    let agent_name = match args.iter().position(|arg| arg == "--agent") {
+        Some(i) => args
+            .get(i + 1)
+            .expect("--agent must be followed by an agent name")
+            .as_str(),
+        None => "heuristic",
+    };
+    let mut agent = named_agent(agent_name, seed, &MctsConfig::default())
+        .unwrap_or_else(|| panic!("unknown agent {}", agent_name));
+    let card_repository = Arc::new(card::load_cards());
+
+    let mut attempts = 0;
+    loop {
+        let user = User { name: name.clone() };
+        let result = Client::connect(address.as_str(), user, card_repository.clone()).and_then(
+            |mut client| {
+                client.play(agent.as_mut())?;
+                Ok(client)
+            },
+        );
+        match result {
+            Ok(client) => {
+                let view = client.view();
+                match view.winning_team {
+                    Some(team_id) if team_id == view.team_id() => {
+                        println!("{} won", name)
+                    }
+                    Some(team_id) => println!("{} lost, team {} won", name, team_id),
+                    None => println!("{} drew", name),
+                }
+                return;
+            }
+            Err(e) if attempts < RECONNECTIONS => {
+                attempts += 1;
+                eprintln!("{}: {}, connecting again", name, e);
+                thread::sleep(Duration::from_secs(1));
+            }
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
+use std::fmt;
 
 /// A deck of cards lists the card definitions with their count
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Deck(pub Vec<(CardDefID, usize)>);
 
 pub type CardDefID = usize;
@@ -22,7 +23,9 @@ pub struct CardMechanics {
 }
 
 #[derive(Debug)]
-pub struct CardDisplay();
+pub struct CardDisplay {
+    pub name: String,
+}
 
 pub type CardRepository = HashMap<CardDefID, CardDefinition>;
 pub fn load_cards() -> CardRepository {
@@ -31,7 +34,202 @@ pub fn load_cards() -> CardRepository {
         CardDefinition {
             id: 101,
             mechanics: CardMechanics { is_token: false, is_land: true},
-            display: CardDisplay(),
+            display: CardDisplay {
+                name: "Forest".to_string(),
+            },
         },
     )])
 }
+
+/// A deck list as players write it: the main deck and the sideboard
+#[derive(Debug, Clone)]
+pub struct DeckList {
+    pub main: Deck,
+    pub sideboard: Deck,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeckListError {
+    /// a line which is neither a count followed by a card name nor a section header
+    InvalidLine(usize, String),
+    /// a card name with no definition in the card repository
+    UnknownCard(usize, String),
+    /// a card definition missing from the card repository, when exporting
+    UnknownDefinition(CardDefID),
+}
+
+impl fmt::Display for DeckListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeckListError::InvalidLine(line, text) => {
+                write!(
+                    f,
+                    "line {}: expected a count and a card name: {}",
+                    line, text
+                )
+            }
+            DeckListError::UnknownCard(line, name) => {
+                write!(f, "line {}: unknown card {}", line, name)
+            }
+            DeckListError::UnknownDefinition(def_id) => {
+                write!(f, "unknown card definition {}", def_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeckListError {}
+
+/// Read a deck list in the text format of `export_deck_list`: one line per card with
+/// its count and name, like `4 Forest`, and after a line `Sideboard` the cards of the
+/// sideboard. Names are matched against the card repository regardless of case.
+/// Empty lines and comments, from `#` or `//` to the end of the line, are ignored.
+pub fn parse_deck_list(
+    text: &str,
+    card_repository: &CardRepository,
+) -> Result<DeckList, DeckListError> {
+    let mut list = DeckList {
+        main: Deck(Vec::new()),
+        sideboard: Deck(Vec::new()),
+    };
+    let mut in_sideboard = false;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let line = line.split("//").next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.trim_end_matches(':').eq_ignore_ascii_case("sideboard") {
+            in_sideboard = true;
+            continue;
+        }
+        let invalid = || DeckListError::InvalidLine(line_number, line.to_string());
+        let (count, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let count: usize = count.parse().map_err(|_| invalid())?;
+        let name = name.trim();
+        let def_id = card_repository
+            .values()
+            .find(|def| def.display.name.eq_ignore_ascii_case(name))
+            .map(|def| def.id)
+            .ok_or_else(|| DeckListError::UnknownCard(line_number, name.to_string()))?;
+        let deck = if in_sideboard {
+            &mut list.sideboard
+        } else {
+            &mut list.main
+        };
+        match deck.0.iter_mut().find(|(id, _)| *id == def_id) {
+            Some((_, total)) => *total += count,
+            None => deck.0.push((def_id, count)),
+        }
+    }
+    Ok(list)
+}
+
+/// Write a deck list in the text format read by `parse_deck_list`
+pub fn export_deck_list(
+    list: &DeckList,
+    card_repository: &CardRepository,
+) -> Result<String, DeckListError> {
+    let lines = |deck: &Deck| {
+        deck.0
+            .iter()
+            .map(|(def_id, count)| match card_repository.get(def_id) {
+                Some(def) => Ok(format!("{} {}\n", count, def.display.name)),
+                None => Err(DeckListError::UnknownDefinition(*def_id)),
+            })
+            .collect::<Result<String, DeckListError>>()
+    };
+    let mut text = lines(&list.main)?;
+    if !list.sideboard.0.is_empty() {
+        text.push_str("\nSideboard\n");
+        text.push_str(&lines(&list.sideboard)?);
+    }
+    Ok(text)
+}
+
+/// The cards of the game and a card which is not a land, to tell cards apart in tests
+#[cfg(test)]
+pub(crate) fn test_cards() -> CardRepository {
+    let mut cards = load_cards();
+    cards.insert(
+        102,
+        CardDefinition {
+            id: 102,
+            mechanics: CardMechanics {
+                is_token: false,
+                is_land: false,
+            },
+            display: CardDisplay {
+                name: "Llanowar Elves".to_string(),
+            },
+        },
+    );
+    cards
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_exported_deck_list_is_parsed_back() {
+        let cards = test_cards();
+        let list = DeckList {
+            main: Deck(vec![(101, 16), (102, 4)]),
+            sideboard: Deck(vec![(102, 2)]),
+        };
+        let text = export_deck_list(&list, &cards).unwrap();
+        assert_eq!(
+            text,
+            "16 Forest\n4 Llanowar Elves\n\nSideboard\n2 Llanowar Elves\n"
+        );
+        let parsed = parse_deck_list(&text, &cards).unwrap();
+        assert_eq!(parsed.main.0, list.main.0);
+        assert_eq!(parsed.sideboard.0, list.sideboard.0);
+    }
+
+    #[test]
+    fn comments_headers_case_and_repeated_lines() {
+        let text = "# my deck\n\
+                    10 forest // basic lands\n\
+                    2 LLANOWAR ELVES\n\
+                    \n\
+                    6 Forest # more lands\n\
+                    Sideboard:\n\
+                    1 Llanowar Elves\n\
+                    1 llanowar elves\n";
+        let list = parse_deck_list(text, &test_cards()).unwrap();
+        assert_eq!(list.main.0, vec![(101, 16), (102, 2)]);
+        assert_eq!(list.sideboard.0, vec![(102, 2)]);
+
+        let list = parse_deck_list("1 Forest\nsideboard\n1 Forest\n", &test_cards()).unwrap();
+        assert_eq!(list.main.0, vec![(101, 1)]);
+        assert_eq!(list.sideboard.0, vec![(101, 1)]);
+    }
+
+    #[test]
+    fn errors_tell_the_line() {
+        let cards = test_cards();
+        assert_eq!(
+            parse_deck_list("4 Forest\n\n2 Island\n", &cards).unwrap_err(),
+            DeckListError::UnknownCard(3, "Island".to_string())
+        );
+        assert_eq!(
+            parse_deck_list("# comment\nForest\n", &cards).unwrap_err(),
+            DeckListError::InvalidLine(2, "Forest".to_string())
+        );
+        assert_eq!(
+            parse_deck_list("four Forest", &cards).unwrap_err(),
+            DeckListError::InvalidLine(1, "four Forest".to_string())
+        );
+        let list = DeckList {
+            main: Deck(vec![(103, 1)]),
+            sideboard: Deck(Vec::new()),
+        };
+        assert_eq!(
+            export_deck_list(&list, &cards).unwrap_err(),
+            DeckListError::UnknownDefinition(103)
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! The client side of the protocol of `server`: a connection to a game server which keeps
+//! the view of its player up to date and lets an agent answer the queries.
+use crate::agent::PlayerAgent;
+use crate::card::CardRepository;
+use crate::server::{ClientMessage, ServerMessage};
+use crate::view::PlayerView;
+use crate::{Message, MessageConsumer, PlayerID, Substep, User};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+
+pub struct Client {
+    player_id: PlayerID,
+    view: PlayerView,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// number of messages sent before the connection which are still to be received
+    resync: usize,
+}
+
+impl Client {
+    /// Connect to a game server and join its game as `user`
+    pub fn connect(
+        address: impl ToSocketAddrs,
+        user: User,
+        card_repository: Arc<CardRepository>,
+    ) -> io::Result<Client> {
+        let writer = TcpStream::connect(address)?;
+        writer.set_nodelay(true)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        let mut client = Client {
+            player_id: 0,
+            view: PlayerView::new(0, card_repository.clone()),
+            reader,
+            writer,
+            resync: 0,
+        };
+        client.send(&ClientMessage::Hello(user))?;
+        match client.receive()? {
+            ServerMessage::Welcome { player_id, resync } => {
+                client.player_id = player_id;
+                client.view = PlayerView::new(player_id, card_repository);
+                client.resync = resync;
+                Ok(client)
+            }
+            ServerMessage::Refused(reason) => {
+                Err(io::Error::new(ErrorKind::ConnectionRefused, reason))
+            }
+            msg => Err(unexpected(&msg)),
+        }
+    }
+
+    pub fn player_id(&self) -> PlayerID {
+        self.player_id
+    }
+
+    pub fn view(&self) -> &PlayerView {
+        &self.view
+    }
+
+    /// Receive the messages of the game until it ends, the queries are answered by `agent`
+    pub fn play(&mut self, agent: &mut dyn PlayerAgent) -> io::Result<()> {
+        while self.view.substep != Substep::GameEnded {
+            let msg = match self.receive()? {
+                ServerMessage::Message(msg) => msg,
+                ServerMessage::Error(error) => return Err(io::Error::other(error)),
+                msg => return Err(unexpected(&msg)),
+            };
+            self.view
+                .handle_message(&msg)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            let answer_now = if self.resync > 0 {
+                // the queries sent before the connection have been answered, except maybe the last
+                self.resync -= 1;
+                self.resync == 0
+            } else {
+                matches!(msg, Message::Query(_, _))
+            };
+            if let (true, Some(query)) = (answer_now, &self.view.maybe_query) {
+                let answer = agent.answer(&self.view, query);
+                self.send(&ClientMessage::Answer(answer))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, msg: &ClientMessage) -> io::Result<()> {
+        let mut line = serde_json::to_string(msg)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())
+    }
+
+    fn receive(&mut self) -> io::Result<ServerMessage> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the server closed the connection",
+            ));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+fn unexpected(msg: &ServerMessage) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("unexpected {:?}", msg))
+}
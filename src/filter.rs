@@ -0,0 +1,202 @@
+use crate::card::CardRepository;
+use crate::{
+    Answer, CardID, Game, HandleError, Message, MessageConsumer, PlayerID, PriorityAction, Query,
+};
+use std::sync::Arc;
+
+/// The messages player `player_id` may see when `msg` has been applied to `game`.
+///
+/// Card ids are replaced by public ids, which change whenever a card enters or leaves
+/// a hidden zone, so a shuffled library tells nothing about its order. A drawn card is
+/// revealed only to its owner, cards entering a public zone are revealed to all, with
+/// their new public id.
+/// The seed is withheld as it would tell the outcome of all future shuffles.
+/// Deck lists are private: a player is told the definitions of their own cards when
+/// they are added, the cards of the other players are added as placeholders.
+/// The public ids the cards get are replaced by the first shuffle.
+pub fn redact(game: &Game, player_id: PlayerID, msg: &Message) -> Vec<Message> {
+    let reveal = |cid| Message::Reveal(game.public_id(cid), game.cards[&cid].def_id);
+    // a card leaving a hidden zone is moved by the id it had there
+    let previous_id = |cid| game.cards[&cid].previous_public_id;
+    let new_id = |cid| Message::NewPublicId(previous_id(cid), game.public_id(cid));
+    match msg {
+        Message::Seed(_) => vec![],
+        Message::Query(pid, query) => {
+            if *pid == player_id {
+                vec![Message::Query(*pid, public_query(game, query))]
+            } else {
+                vec![]
+            }
+        }
+        Message::AddCard {
+            id,
+            owner_id,
+            def_id,
+        } => {
+            if *owner_id == player_id {
+                vec![Message::AddCard {
+                    id: game.public_id(*id),
+                    owner_id: *owner_id,
+                    def_id: *def_id,
+                }]
+            } else {
+                vec![Message::AddHiddenCard {
+                    id: game.public_id(*id),
+                    owner_id: *owner_id,
+                }]
+            }
+        }
+        Message::ShuffleLibrary(pid, order) => vec![Message::ShuffleLibrary(
+            *pid,
+            order.iter().map(|cid| game.public_id(*cid)).collect(),
+        )],
+        Message::DrawCard(pid, cid) => {
+            let mut msg = vec![Message::DrawCard(*pid, game.public_id(*cid))];
+            if *pid == player_id {
+                msg.push(reveal(*cid));
+            }
+            msg
+        }
+        Message::Discard(pid, cid) => vec![
+            Message::Discard(*pid, previous_id(*cid)),
+            new_id(*cid),
+            reveal(*cid),
+        ],
+        Message::PlayLand(pid, cid, oid) => vec![
+            Message::PlayLand(*pid, previous_id(*cid), *oid),
+            new_id(*cid),
+            reveal(*cid),
+        ],
+        Message::RemoveFromGame(cid) => vec![Message::RemoveFromGame(game.public_id(*cid))],
+        _ => vec![msg.clone()],
+    }
+}
+
+/// `query` with all card ids replaced by public ids
+pub fn public_query(game: &Game, query: &Query) -> Query {
+    match query {
+        Query::Discard(cards, n) => {
+            Query::Discard(cards.iter().map(|cid| game.public_id(*cid)).collect(), *n)
+        }
+        Query::PriorityAction(actions) => Query::PriorityAction(
+            actions
+                .iter()
+                .map(|action| match action {
+                    PriorityAction::Pass => PriorityAction::Pass,
+                    PriorityAction::PlayLand(cid) => PriorityAction::PlayLand(game.public_id(*cid)),
+                })
+                .collect(),
+        ),
+        Query::AllowUndo(pid) => Query::AllowUndo(*pid),
+        // the queries between the games of a match are not about cards in a game
+        Query::PlayFirst | Query::Sideboard(_, _) => query.clone(),
+    }
+}
+
+/// `answer` given in public ids with all card ids restored.
+/// Ids which belong to no card are mapped to an invalid id, so the answer gets rejected.
+pub fn private_answer(game: &Game, answer: &Answer) -> Answer {
+    let card_id = |public_id| game.card_id_from_public(public_id).unwrap_or(CardID::MAX);
+    match answer {
+        Answer::Discard(cards) => Answer::Discard(cards.iter().map(|pid| card_id(*pid)).collect()),
+        Answer::PriorityAction(PriorityAction::Pass) => {
+            Answer::PriorityAction(PriorityAction::Pass)
+        }
+        Answer::PriorityAction(PriorityAction::PlayLand(pid)) => {
+            Answer::PriorityAction(PriorityAction::PlayLand(card_id(*pid)))
+        }
+        Answer::Undo => Answer::Undo,
+        Answer::AllowUndo(allowed) => Answer::AllowUndo(*allowed),
+        Answer::PlayFirst(_) | Answer::Sideboard(_) => answer.clone(),
+    }
+}
+
+/// A message consumer passing on only what one player may see to another consumer.
+/// It keeps its own copy of the game to know which cards the messages are about.
+pub struct PlayerFilter {
+    player_id: PlayerID,
+    game: Game,
+    consumer: Box<dyn MessageConsumer>,
+}
+
+impl PlayerFilter {
+    pub fn new(
+        player_id: PlayerID,
+        card_repository: Arc<CardRepository>,
+        consumer: Box<dyn MessageConsumer>,
+    ) -> Self {
+        PlayerFilter {
+            player_id,
+            game: Game::new(card_repository),
+            consumer,
+        }
+    }
+}
+
+impl MessageConsumer for PlayerFilter {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        self.game.handle_message(msg)?;
+        for msg in redact(&self.game, self.player_id, msg) {
+            self.consumer.handle_message(&msg)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::load_cards;
+    use crate::scenario::Scenario;
+    use crate::view::PlayerView;
+
+    #[test]
+    fn the_cards_of_other_players_are_added_without_their_definition() {
+        let mut game = Scenario::new(Arc::new(load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .build()
+            .unwrap();
+        let mut view = PlayerView::from_game(&game, 0);
+        for owner_id in [0, 1] {
+            let msg = Message::AddCard {
+                id: game.get_id(),
+                owner_id,
+                def_id: 101,
+            };
+            game.handle_message(&msg).unwrap();
+            for msg in redact(&game, 0, &msg) {
+                view.handle_message(&msg).unwrap();
+            }
+        }
+        let definitions: Vec<_> = view.cards.values().map(|card| card.def_id).collect();
+        assert_eq!(definitions, vec![Some(101), None]);
+        assert_eq!(view.deck_list, vec![101]);
+    }
+
+    #[test]
+    fn a_discarded_card_is_known_by_a_new_id() {
+        let mut game = Scenario::new(Arc::new(load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[101; 2])
+            .build()
+            .unwrap();
+        let mut view = PlayerView::from_game(&game, 1);
+        let card_id = game.hand(0)[0];
+        let id_in_hand = view.players[0].hand()[0];
+        assert_eq!(id_in_hand, game.public_id(card_id));
+
+        let msg = Message::Discard(0, card_id);
+        game.handle_message(&msg).unwrap();
+        for msg in redact(&game, 1, &msg) {
+            view.handle_message(&msg).unwrap();
+        }
+        let id_in_graveyard = view.players[0].graveyard()[0];
+        assert_ne!(id_in_graveyard, id_in_hand);
+        assert_eq!(id_in_graveyard, game.public_id(card_id));
+        assert!(!view.cards.contains_key(&id_in_hand));
+        assert_eq!(view.cards[&id_in_graveyard].def_id, Some(101));
+        assert_eq!(view.players[0].hand().len(), 1);
+    }
+}
@@ -0,0 +1,136 @@
+//! Game logs store the messages of a game in a file, one JSON object per line.
+//!
+//! The first line is a header identifying the format and its version,
+//! every following line is a `Message` as serialized by serde. Changes to the
+//! messages which would make existing logs unreadable need a new version.
+use crate::{HandleError, Message, MessageConsumer, Substep};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+pub const LOG_FORMAT: &str = "bote-game-log";
+pub const LOG_VERSION: u32 = 2;
+
+/// The first line of a game log
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogHeader {
+    pub format: String,
+    pub version: u32,
+}
+
+/// A message consumer which writes all messages to a game log
+pub struct GameLogWriter {
+    writer: BufWriter<File>,
+}
+
+impl GameLogWriter {
+    /// Create the log file at `path`, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = LogHeader {
+            format: LOG_FORMAT.to_string(),
+            version: LOG_VERSION,
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writeln!(writer)?;
+        Ok(GameLogWriter { writer })
+    }
+}
+
+impl MessageConsumer for GameLogWriter {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        serde_json::to_writer(&mut self.writer, msg).map_err(|e| HandleError::IoError(e.into()))?;
+        writeln!(self.writer).map_err(HandleError::IoError)?;
+        if let Message::Substep(Substep::GameEnded) = msg {
+            self.writer.flush().map_err(HandleError::IoError)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read all messages of the game log at `path`
+pub fn read_game_log<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Message>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: LogHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(Error::new(ErrorKind::InvalidData, "empty game log")),
+    };
+    if header.format != LOG_FORMAT || header.version != LOG_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "unsupported game log format {} version {}",
+                header.format, header.version
+            ),
+        ));
+    }
+    let mut messages = Vec::new();
+    for line in lines {
+        messages.push(serde_json::from_str(&line?)?);
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{PlayerAgent, RandomAgent};
+    use crate::card::{load_cards, Deck};
+    use crate::{duel, Game, User};
+    use std::sync::Arc;
+
+    fn temporary_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bote-{}-{}.log", name, std::process::id()))
+    }
+
+    #[test]
+    fn a_logged_game_is_played_again_from_its_log() {
+        let path = temporary_path("logged");
+        let card_repository = Arc::new(load_cards());
+        let mut consumers: Vec<Box<dyn MessageConsumer>> =
+            vec![Box::new(GameLogWriter::create(&path).unwrap())];
+        let mut agents: Vec<Box<dyn PlayerAgent>> =
+            vec![Box::new(RandomAgent::new(1)), Box::new(RandomAgent::new(2))];
+        let user = |name: &str| User {
+            name: name.to_string(),
+        };
+        let played = duel(
+            user("Leo"),
+            Deck(vec![(101, 12)]),
+            user("Marc"),
+            Deck(vec![(101, 12)]),
+            &mut agents,
+            &mut consumers,
+            card_repository.clone(),
+            9,
+        )
+        .unwrap();
+        drop(consumers);
+
+        let messages = read_game_log(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut game = Game::new(card_repository);
+        for msg in &messages {
+            game.handle_message(msg).unwrap();
+        }
+        assert_eq!(
+            serde_json::to_string(&game).unwrap(),
+            serde_json::to_string(&played).unwrap()
+        );
+    }
+
+    #[test]
+    fn a_log_of_another_version_is_rejected() {
+        let path = temporary_path("version");
+        let header = LogHeader {
+            format: LOG_FORMAT.to_string(),
+            version: LOG_VERSION + 1,
+        };
+        let text = format!("{}\n", serde_json::to_string(&header).unwrap());
+        std::fs::write(&path, text).unwrap();
+        let error = read_game_log(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
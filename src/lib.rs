@@ -0,0 +1,2186 @@
+#![allow(dead_code)]
+
+use crate::agent::PlayerAgent;
+use crate::card::{CardDefID, CardDefinition};
+use crate::view::PlayerView;
+//use crate::energy::Energy;
+pub mod agent;
+pub mod card;
+pub mod client;
+//mod energy;
+pub mod filter;
+pub mod gamelog;
+pub mod matches;
+pub mod mcts;
+pub mod replay;
+pub mod save;
+pub mod scenario;
+pub mod server;
+pub mod terminal;
+pub mod view;
+use rand::seq::SliceRandom; // Vec.shuffle
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+pub trait MessageConsumer {
+    fn handle_message(&mut self, _: &Message) -> Result<(), HandleError>;
+}
+
+pub struct MessageLogger();
+
+impl MessageConsumer for MessageLogger {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        println!("{:?}", msg);
+        Ok(())
+    }
+}
+
+/// A message consumer checking `Game::check_invariants` after every message,
+/// on its own copy of the game. Debug builds check the game itself after every message,
+/// this is for release builds, as in `simulate --check`. A message breaking an invariant
+/// is answered with an error, which the game ignores as it does all errors of consumers,
+/// so it is up to the owner of the consumers to look for it.
+pub struct InvariantChecker(Game);
+
+impl InvariantChecker {
+    pub fn new(card_repository: Arc<card::CardRepository>) -> Self {
+        InvariantChecker(Game::new(card_repository))
+    }
+}
+
+impl MessageConsumer for InvariantChecker {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        self.0.handle_message(msg)?;
+        self.0
+            .check_invariants()
+            .map_err(|e| HandleError::InvalidMessage(Box::new(msg.clone()), Box::new(e)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PriorityAction {
+    Pass,
+    PlayLand(CardID),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Query {
+    Discard(Vec<CardID>, i32),
+    PriorityAction(Vec<PriorityAction>),
+    /// whether the player may take back their last decision
+    AllowUndo(PlayerID),
+    /// whether the player takes the first turn of the next game of a match
+    PlayFirst,
+    /// the main deck and the sideboard of the player between the games of a match
+    Sideboard(Vec<(CardDefID, usize)>, Vec<(CardDefID, usize)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Answer {
+    Discard(Vec<CardID>),
+    PriorityAction(PriorityAction),
+    /// instead of answering, take back the last decision, if the opponents agree
+    Undo,
+    AllowUndo(bool),
+    PlayFirst(bool),
+    /// the main deck for the next game, the remaining cards are the sideboard
+    Sideboard(Vec<(CardDefID, usize)>),
+}
+
+pub fn validate_answer(query: &Query, answer: &Answer) -> bool {
+    match (query, answer) {
+        (Query::Discard(cards, n), Answer::Discard(selected)) => {
+            // each card can be discarded once
+            selected.len() as i32 == *n
+                && selected
+                    .iter()
+                    .enumerate()
+                    .all(|(i, c)| cards.contains(c) && !selected[..i].contains(c))
+        }
+        (Query::PriorityAction(actions), Answer::PriorityAction(action)) => {
+            actions.contains(action)
+        }
+        (Query::AllowUndo(_), Answer::AllowUndo(_)) => true,
+        (Query::PlayFirst, Answer::PlayFirst(_)) => true,
+        (Query::Sideboard(main, sideboard), Answer::Sideboard(new_main)) => {
+            let total = |cards: &[(CardDefID, usize)]| cards.iter().map(|(_, n)| n).sum::<usize>();
+            let count = |cards: &[(CardDefID, usize)], def_id| {
+                cards
+                    .iter()
+                    .filter(|(d, _)| *d == def_id)
+                    .map(|(_, n)| n)
+                    .sum::<usize>()
+            };
+            // cards are swapped, the main deck keeps its size
+            total(new_main) == total(main)
+                && new_main.iter().all(|(def_id, _)| {
+                    count(new_main, *def_id) <= count(main, *def_id) + count(sideboard, *def_id)
+                })
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+}
+
+/// The random number generator of the game.
+/// Its output only depends on the seed, so games can be reproduced on any platform.
+pub type GameRng = rand_chacha::ChaCha8Rng;
+
+pub type CardID = usize;
+/// The id under which a card is known to the players.
+/// A card gets a new public id whenever it enters or leaves a hidden zone,
+/// so that it can not be followed through the library or a hand.
+pub type PublicCardID = usize;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Card {
+    id: CardID,
+    public_id: PublicCardID,
+    /// the public id the card had before its last zone change
+    #[serde(default)]
+    previous_public_id: PublicCardID,
+    object_id: Option<ObjectID>,
+    owner_id: PlayerID,
+    def_id: CardDefID,
+}
+
+pub type ObjectID = usize;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Object {
+    id: ObjectID,
+    controller_id: PlayerID,
+    kind: ObjectKind,
+    location: ObjectLocation,
+}
+
+impl Object {
+    pub fn id(&self) -> ObjectID {
+        self.id
+    }
+
+    pub fn controller_id(&self) -> PlayerID {
+        self.controller_id
+    }
+
+    pub fn kind(&self) -> &ObjectKind {
+        &self.kind
+    }
+
+    pub fn location(&self) -> &ObjectLocation {
+        &self.location
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ObjectLocation {
+    Library,
+    Hand,
+    Stack,
+    Battlefield,
+    Graveyard,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ObjectKind {
+    Card(CardID),
+    ActivatedAbility(CardID, usize),
+}
+
+pub type PlayerID = usize;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Player {
+    id: PlayerID,
+    name: String,
+    team_id: TeamID,
+    library: Vec<CardID>,
+    hand: Vec<CardID>,
+    graveyard: Vec<CardID>,
+    /// the cards which have left the game, such as those of a player who lost
+    #[serde(default)]
+    removed: Vec<CardID>,
+    has_drawn_from_empty: bool,
+    has_lost: bool,
+    has_passed: bool,
+    lands_played: u32,
+}
+
+impl Player {
+    fn new(id: PlayerID, name: String, team_id: TeamID) -> Player {
+        Player {
+            id,
+            name,
+            team_id,
+            library: Vec::new(),
+            hand: Vec::new(),
+            graveyard: Vec::new(),
+            removed: Vec::new(),
+            has_drawn_from_empty: false,
+            has_lost: false,
+            has_passed: false,
+            lands_played: 0,
+        }
+    }
+
+    fn max_hand_size(&self) -> i32 {
+        7
+    }
+
+    pub fn id(&self) -> PlayerID {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn team_id(&self) -> TeamID {
+        self.team_id
+    }
+
+    /// The cards of the library, the top card last
+    pub fn library(&self) -> &[CardID] {
+        &self.library
+    }
+
+    pub fn hand(&self) -> &[CardID] {
+        &self.hand
+    }
+
+    pub fn graveyard(&self) -> &[CardID] {
+        &self.graveyard
+    }
+
+    /// The cards which have left the game
+    pub fn removed(&self) -> &[CardID] {
+        &self.removed
+    }
+
+    pub fn has_lost(&self) -> bool {
+        self.has_lost
+    }
+
+    pub fn has_passed(&self) -> bool {
+        self.has_passed
+    }
+
+    pub fn lands_played(&self) -> u32 {
+        self.lands_played
+    }
+}
+
+pub type TeamID = usize;
+/// Players of a team share their life total and take their turns together.
+/// In a free-for-all game every player forms a team of their own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Team {
+    id: TeamID,
+    player_ids: Vec<PlayerID>,
+    life: i32,
+}
+
+impl Team {
+    fn new(id: TeamID, life: i32) -> Team {
+        Team {
+            id,
+            player_ids: Vec::new(),
+            life,
+        }
+    }
+
+    /// Each additional teammate adds 10 life, so a two-headed giant team starts at 30
+    fn starting_life(team_size: usize) -> i32 {
+        10 + 10 * team_size as i32
+    }
+
+    pub fn id(&self) -> TeamID {
+        self.id
+    }
+
+    pub fn player_ids(&self) -> &[PlayerID] {
+        &self.player_ids
+    }
+
+    pub fn life(&self) -> i32 {
+        self.life
+    }
+}
+
+pub type SpellID = usize;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spell {
+    id: SpellID,
+}
+impl Spell {
+    pub fn id(&self) -> SpellID {
+        self.id
+    }
+
+    fn resolve(&self) -> Vec<Message> {
+        Vec::new()
+    }
+}
+
+/// The complete state of a game.
+/// Card definitions are shared with other games through the card repository,
+/// so a game is cheap to clone for searching and can be sent to other threads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Game {
+    cards: BTreeMap<CardID, Card>,
+    players: Vec<Player>,
+    teams: Vec<Team>,
+    substep: Substep,
+    step: Step,
+    active_player_id: usize,
+    priority_player_id: usize,
+    /// not saved with the game, see `save::load_game`
+    #[serde(skip)]
+    card_repository: Arc<card::CardRepository>,
+    seed: u64,
+    rng: GameRng,
+    stack: Vec<Spell>,
+    maybe_query: Option<(PlayerID, Query)>,
+    maybe_answer: Option<Answer>,
+    next_id: usize,
+    next_public_id: PublicCardID,
+    objects: BTreeMap<ObjectID, Object>,
+    /// the game as it was before each accepted answer, with the player who answered
+    #[serde(skip)]
+    undo: UndoStack<(PlayerID, Game)>,
+}
+
+/// Number of answers which can be taken back in games with an agent which takes back answers
+const MAX_UNDO: usize = 100;
+
+/// Number of invalid answers to a query asked outside of the steps of the game,
+/// such as whether an answer may be taken back, after which the game is stopped
+const MAX_REJECTED_ANSWERS: usize = 10;
+
+/// Copies of a game state taken to go back to them later.
+/// Only the latest copies up to a limit are kept, a limit of 0 disables taking copies.
+#[derive(Clone)]
+pub struct UndoStack<T> {
+    snapshots: VecDeque<T>,
+    limit: usize,
+}
+
+impl<T> UndoStack<T> {
+    pub fn new(limit: usize) -> Self {
+        UndoStack {
+            snapshots: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Whether snapshots are kept, so that they are not taken for nothing
+    fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    /// Keep up to `limit` snapshots from now on, dropping the oldest ones above it
+    fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.snapshots.len() > limit {
+            self.snapshots.pop_front();
+        }
+    }
+
+    fn push(&mut self, snapshot: T) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.snapshots.len() == self.limit {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Remove the last `n` snapshots and return the oldest of them
+    fn pop(&mut self, n: usize) -> Option<T> {
+        if n == 0 || n > self.snapshots.len() {
+            return None;
+        }
+        let position = self.snapshots.len() - n;
+        self.snapshots.truncate(position + 1);
+        self.snapshots.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+/// Taking back answers is disabled until a `Message::UndoLimit` enables it
+impl<T> Default for UndoStack<T> {
+    fn default() -> Self {
+        UndoStack::new(0)
+    }
+}
+
+impl<T> fmt::Debug for UndoStack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UndoStack({} snapshots)", self.snapshots.len())
+    }
+}
+
+impl Game {
+    fn new(card_repository: Arc<card::CardRepository>) -> Game {
+        Game {
+            cards: BTreeMap::new(),
+            players: Vec::new(),
+            teams: Vec::new(),
+            substep: Substep::InitialShuffle,
+            step: Step::Untap,
+            active_player_id: 0,
+            priority_player_id: 0,
+            card_repository,
+            seed: 0,
+            rng: GameRng::seed_from_u64(0),
+            stack: Vec::new(),
+            next_id: 1001,
+            next_public_id: 1,
+            objects: BTreeMap::new(),
+            maybe_answer: None,
+            maybe_query: None,
+            undo: UndoStack::default(),
+        }
+    }
+
+    fn definition(&self, card_id: CardID) -> &CardDefinition {
+        &self.card_repository[&self.cards[&card_id].def_id]
+    }
+
+    /// Give the card a new public id, e.g. when it enters a hidden zone
+    fn assign_public_id(&mut self, card_id: CardID) {
+        if let Some(card) = self.cards.get_mut(&card_id) {
+            card.previous_public_id = card.public_id;
+            card.public_id = self.next_public_id;
+            self.next_public_id += 1;
+        }
+    }
+
+    /// The id by which the players know a card, as used by agents and in recorded answers
+    pub fn public_id(&self, card_id: CardID) -> PublicCardID {
+        self.cards[&card_id].public_id
+    }
+
+    fn card_id_from_public(&self, public_id: PublicCardID) -> Option<CardID> {
+        self.cards
+            .values()
+            .find(|card| card.public_id == public_id)
+            .map(|card| card.id)
+    }
+
+    fn get_id(&mut self) -> usize {
+        let res = self.next_id;
+        self.next_id += 1;
+        res
+    }
+
+    fn commit_id(&mut self, id: usize) {
+        assert!(id >= self.next_id);
+        self.next_id = id + 1;
+    }
+
+    /// The first player in turn order, starting at `pid`, who has not lost yet
+    fn player_in_game_from(&self, pid: PlayerID) -> PlayerID {
+        let n = self.players.len();
+        (0..n)
+            .map(|i| (pid + i) % n)
+            .find(|id| !self.players[*id].has_lost)
+            .unwrap_or(pid)
+    }
+
+    /// The player following `pid` in turn order, skipping players who have lost
+    fn next_player_id(&self, pid: PlayerID) -> PlayerID {
+        self.player_in_game_from((pid + 1) % self.players.len())
+    }
+
+    /// The first player of the next team in turn order which is still in the game
+    fn next_team_player_id(&self, pid: PlayerID) -> PlayerID {
+        let n = self.players.len();
+        let team_id = self.players[pid].team_id;
+        (1..n)
+            .map(|i| (pid + i) % n)
+            .find(|id| {
+                let player = &self.players[*id];
+                player.team_id != team_id && !player.has_lost
+            })
+            .unwrap_or(pid)
+    }
+
+    /// All players of the active team are active players
+    fn is_active_player(&self, pid: PlayerID) -> bool {
+        self.players[pid].team_id == self.players[self.active_player_id].team_id
+    }
+
+    fn team_has_lost(&self, team: &Team) -> bool {
+        team.player_ids
+            .iter()
+            .all(|pid| self.players[*pid].has_lost)
+    }
+
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    pub fn substep(&self) -> Substep {
+        self.substep
+    }
+
+    /// The life total of a team
+    pub fn life(&self, team_id: TeamID) -> i32 {
+        self.teams[team_id].life
+    }
+
+    pub fn has_lost(&self, player_id: PlayerID) -> bool {
+        self.players[player_id].has_lost
+    }
+
+    /// The library of a player, the top card last
+    pub fn library(&self, player_id: PlayerID) -> &[CardID] {
+        &self.players[player_id].library
+    }
+
+    pub fn hand(&self, player_id: PlayerID) -> &[CardID] {
+        &self.players[player_id].hand
+    }
+
+    pub fn graveyard(&self, player_id: PlayerID) -> &[CardID] {
+        &self.players[player_id].graveyard
+    }
+
+    /// The cards on the battlefield under the control of a player
+    pub fn battlefield(&self, player_id: PlayerID) -> Vec<CardID> {
+        self.objects
+            .values()
+            .filter(|object| object.controller_id == player_id)
+            .filter_map(|object| match object.kind {
+                ObjectKind::Card(card_id) => Some(card_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The teams with at least one player still in the game
+    fn remaining_teams(&self) -> impl Iterator<Item = &Team> {
+        self.teams.iter().filter(|t| !self.team_has_lost(t))
+    }
+
+    /// The only team left in the game, if there is exactly one
+    pub fn winning_team(&self) -> Option<TeamID> {
+        let mut remaining_teams = self.remaining_teams();
+        match (remaining_teams.next(), remaining_teams.next()) {
+            (Some(winner), None) => Some(winner.id),
+            _ => None,
+        }
+    }
+
+    /// Number of answers to take back so that the last decision of the player is undone
+    fn answers_to_undo(&self, player_id: PlayerID) -> Option<usize> {
+        let snapshots = &self.undo.snapshots;
+        snapshots
+            .iter()
+            .rposition(|(pid, _)| *pid == player_id)
+            .map(|i| snapshots.len() - i)
+    }
+
+    /// Check the rules every message must preserve: teams and players have the id of their
+    /// place in the game and every player is listed by their team, and no other team,
+    /// each card is in exactly one zone,
+    /// counting the cards removed from the game, objects have unique ids below `next_id`
+    /// and belong to their card, the active and priority players exist, the priority player
+    /// is still in the game unless no player is, and a pending query is for a player
+    /// still in the game, the priority player if it is a priority action, about cards of
+    /// the game. Once the game has ended the queries between the games of a match are for
+    /// any player.
+    pub fn check_invariants(&self) -> Result<(), HandleError> {
+        for (team_id, team) in self.teams.iter().enumerate() {
+            if team.id != team_id {
+                return Err(HandleError::TeamIdError(team.id));
+            }
+            if let Some(pid) = team
+                .player_ids
+                .iter()
+                .find(|pid| **pid >= self.players.len())
+            {
+                return Err(HandleError::PlayerIdError(*pid));
+            }
+        }
+        for (player_id, player) in self.players.iter().enumerate() {
+            let listed = self
+                .teams
+                .iter()
+                .flat_map(|team| &team.player_ids)
+                .filter(|pid| **pid == player_id)
+                .count();
+            let in_own_team = self
+                .teams
+                .get(player.team_id)
+                .is_some_and(|team| team.player_ids.contains(&player_id));
+            if player.id != player_id || listed != 1 || !in_own_team {
+                return Err(HandleError::PlayerIdError(player.id));
+            }
+        }
+        let mut seen = BTreeSet::new();
+        let in_player_zones = self.players.iter().flat_map(|p| {
+            p.library
+                .iter()
+                .chain(&p.hand)
+                .chain(&p.graveyard)
+                .chain(&p.removed)
+                .copied()
+        });
+        let on_battlefield = self
+            .objects
+            .values()
+            .filter_map(|object| match object.kind {
+                ObjectKind::Card(card_id) => Some(card_id),
+                _ => None,
+            });
+        for card_id in in_player_zones.chain(on_battlefield) {
+            if !self.cards.contains_key(&card_id) || !seen.insert(card_id) {
+                return Err(HandleError::CardIdError(card_id));
+            }
+        }
+        for card in self.cards.values() {
+            if card.owner_id >= self.players.len() {
+                return Err(HandleError::PlayerIdError(card.owner_id));
+            }
+            if !seen.contains(&card.id) {
+                return Err(HandleError::CardIdError(card.id));
+            }
+            if let Some(oid) = card.object_id {
+                if !self.objects.contains_key(&oid) {
+                    return Err(HandleError::ObjectIdError(oid));
+                }
+            }
+        }
+        for (oid, object) in &self.objects {
+            if *oid != object.id || *oid >= self.next_id {
+                return Err(HandleError::ObjectIdError(*oid));
+            }
+            if object.controller_id >= self.players.len() {
+                return Err(HandleError::PlayerIdError(object.controller_id));
+            }
+            if let ObjectKind::Card(card_id) = object.kind {
+                if self.cards[&card_id].object_id != Some(*oid) {
+                    return Err(HandleError::ObjectIdError(*oid));
+                }
+            }
+        }
+        // before the players are created both ids are 0
+        if !self.players.is_empty() {
+            for pid in [self.active_player_id, self.priority_player_id] {
+                if pid >= self.players.len() {
+                    return Err(HandleError::PlayerIdError(pid));
+                }
+            }
+            let all_lost = self.players.iter().all(|p| p.has_lost);
+            if self.players[self.priority_player_id].has_lost && !all_lost {
+                return Err(HandleError::PlayerIdError(self.priority_player_id));
+            }
+        }
+        if let Some((pid, query)) = &self.maybe_query {
+            let asked = self
+                .players
+                .get(*pid)
+                .ok_or(HandleError::PlayerIdError(*pid))?;
+            let priority = matches!(query, Query::PriorityAction(_));
+            let in_game = !asked.has_lost || self.substep == Substep::GameEnded;
+            if !in_game || (priority && *pid != self.priority_player_id) {
+                return Err(HandleError::PlayerIdError(*pid));
+            }
+            let card_ids: Vec<CardID> = match query {
+                Query::PriorityAction(actions) => actions
+                    .iter()
+                    .filter_map(|action| match action {
+                        PriorityAction::PlayLand(card_id) => Some(*card_id),
+                        PriorityAction::Pass => None,
+                    })
+                    .collect(),
+                Query::Discard(card_ids, _) => card_ids.clone(),
+                _ => Vec::new(),
+            };
+            if let Some(card_id) = card_ids.iter().find(|cid| !self.cards.contains_key(cid)) {
+                return Err(HandleError::CardIdError(*card_id));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Query(PlayerID, Query),
+    RejectAnswer,
+    AcceptAnswer,
+    CreateTeam {
+        id: TeamID,
+        life: i32,
+    },
+    CreatePlayer {
+        id: PlayerID,
+        name: String,
+        team_id: TeamID,
+    },
+    AddCard {
+        id: CardID,
+        owner_id: PlayerID,
+        def_id: card::CardDefID,
+    },
+    Substep(Substep),
+    Step(Step),
+    BeginTurn(PlayerID),
+    GetPriority(PlayerID),
+    Seed(u64),
+    /// the library of the player in its new order, from bottom to top
+    ShuffleLibrary(PlayerID, Vec<CardID>),
+    DrawCard(PlayerID, CardID),
+    DrawFromEmpty(PlayerID),
+    PlayerLoses(PlayerID, LossReason),
+    TeamWins(TeamID),
+    PlayerHasPriority(PlayerID),
+    PlayerPasses(PlayerID),
+    PriorityEnded,
+    ResolveSpell(SpellID),
+    Discard(PlayerID, CardID),
+    PlayLand(PlayerID, CardID, ObjectID),
+    RemoveObject(ObjectID),
+    /// the card leaves the game from any zone, it is put with the removed cards of its owner
+    RemoveFromGame(CardID),
+    /// only in the stream of a player: a card of another player, added without its definition
+    AddHiddenCard {
+        id: PublicCardID,
+        owner_id: PlayerID,
+    },
+    /// only in the stream of a player: the definition of a card which became visible
+    Reveal(PublicCardID, CardDefID),
+    /// only in the stream of a player: the card known by the first public id has left a
+    /// hidden zone and is known by the second one from now on
+    NewPublicId(PublicCardID, PublicCardID),
+    /// the answer of a player to `Query::AllowUndo`
+    AllowUndo(PlayerID, bool),
+    /// take back the last accepted answers, the query of the oldest of them is asked again
+    Rollback(usize),
+    /// how many of the answers from now on can be taken back, none until this message
+    UndoLimit(usize),
+}
+
+/// Why a message consumer could not handle a message
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum HandleError {
+    PlayerIdError(PlayerID),
+    CardIdError(CardID),
+    CardDefIdError(CardDefID),
+    ObjectIdError(ObjectID),
+    TeamIdError(TeamID),
+    SpellIdError(SpellID),
+    /// The cards of a message do not match the library of the player
+    LibraryError(PlayerID),
+    /// There are not as many answers to take back
+    RollbackError(usize),
+    /// The player gave invalid answers to a query again and again
+    AnswerError(PlayerID),
+    IoError(std::io::Error),
+    /// The message could not be applied to the game for the given reason
+    InvalidMessage(Box<Message>, Box<HandleError>),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::PlayerIdError(id) => write!(f, "invalid player id {}", id),
+            HandleError::CardIdError(id) => write!(f, "invalid card id {}", id),
+            HandleError::CardDefIdError(id) => write!(f, "unknown card definition {}", id),
+            HandleError::ObjectIdError(id) => write!(f, "invalid object id {}", id),
+            HandleError::TeamIdError(id) => write!(f, "invalid team id {}", id),
+            HandleError::SpellIdError(id) => write!(f, "spell {} is not on top of the stack", id),
+            HandleError::LibraryError(id) => {
+                write!(f, "the cards do not match the library of player {}", id)
+            }
+            HandleError::RollbackError(n) => write!(f, "can not take back {} answers", n),
+            HandleError::AnswerError(id) => write!(f, "player {} keeps giving invalid answers", id),
+            HandleError::IoError(e) => write!(f, "{}", e),
+            HandleError::InvalidMessage(msg, e) => write!(f, "can not apply {:?}: {}", msg, e),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HandleError::IoError(e) => Some(e),
+            HandleError::InvalidMessage(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for HandleError {
+    fn from(e: std::io::Error) -> Self {
+        HandleError::IoError(e)
+    }
+}
+
+impl MessageConsumer for Game {
+    fn handle_message(&mut self, message: &Message) -> Result<(), HandleError> {
+        self.apply(message)
+            .map_err(|e| HandleError::InvalidMessage(Box::new(message.clone()), Box::new(e)))
+    }
+}
+
+impl Game {
+    fn player_mut(&mut self, player_id: PlayerID) -> Result<&mut Player, HandleError> {
+        self.players
+            .get_mut(player_id)
+            .ok_or(HandleError::PlayerIdError(player_id))
+    }
+
+    fn apply(&mut self, message: &Message) -> Result<(), HandleError> {
+        match message {
+            Message::Query(pid, query) => {
+                self.player_mut(*pid)?;
+                self.maybe_query = Some((*pid, query.clone()));
+                self.maybe_answer = None;
+                Ok(())
+            }
+            Message::AcceptAnswer => {
+                if let Some((pid, _)) = self.maybe_query.as_ref().filter(|_| self.undo.is_enabled())
+                {
+                    let pid = *pid;
+                    let undo = std::mem::take(&mut self.undo);
+                    let snapshot = self.clone();
+                    self.undo = undo;
+                    self.undo.push((pid, snapshot));
+                }
+                self.maybe_query = None;
+                self.maybe_answer = None;
+                Ok(())
+            }
+            Message::AllowUndo(pid, _) => {
+                self.player_mut(*pid)?;
+                self.maybe_query = None;
+                self.maybe_answer = None;
+                Ok(())
+            }
+            Message::UndoLimit(limit) => {
+                self.undo.set_limit(*limit);
+                Ok(())
+            }
+            Message::Rollback(n) => {
+                let mut undo = std::mem::take(&mut self.undo);
+                match undo.pop(*n) {
+                    Some((_, snapshot)) => {
+                        *self = snapshot;
+                        self.undo = undo;
+                        self.maybe_answer = None;
+                        Ok(())
+                    }
+                    None => {
+                        self.undo = undo;
+                        Err(HandleError::RollbackError(*n))
+                    }
+                }
+            }
+            Message::RejectAnswer => {
+                self.maybe_query = None;
+                self.maybe_answer = None;
+                Ok(())
+            }
+            Message::CreateTeam { id, life } => {
+                if self.teams.len() != *id {
+                    Err(HandleError::TeamIdError(*id))
+                } else {
+                    self.teams.push(Team::new(*id, *life));
+                    Ok(())
+                }
+            }
+            Message::CreatePlayer { id, name, team_id } => {
+                if self.players.len() != *id {
+                    Err(HandleError::PlayerIdError(*id))
+                } else if let Some(team) = self.teams.get_mut(*team_id) {
+                    team.player_ids.push(*id);
+                    let player = Player::new(*id, name.clone(), *team_id);
+                    self.players.push(player);
+                    Ok(())
+                } else {
+                    Err(HandleError::TeamIdError(*team_id))
+                }
+            }
+            Message::AddCard {
+                id,
+                owner_id,
+                def_id,
+            } => {
+                if !self.card_repository.contains_key(def_id) {
+                    return Err(HandleError::CardDefIdError(*def_id));
+                }
+                if self.cards.contains_key(id) {
+                    return Err(HandleError::CardIdError(*id));
+                }
+                self.player_mut(*owner_id)?.library.push(*id);
+                let card = Card {
+                    id: *id,
+                    public_id: 0,
+                    previous_public_id: 0,
+                    owner_id: *owner_id,
+                    object_id: None,
+                    def_id: *def_id,
+                };
+                self.cards.insert(*id, card);
+                self.assign_public_id(*id);
+                Ok(())
+            }
+            Message::Substep(s) => {
+                self.substep = *s;
+                Ok(())
+            }
+            Message::Step(s) => {
+                self.step = *s;
+                Ok(())
+            }
+            Message::BeginTurn(pid) => {
+                let team_id = self.player_mut(*pid)?.team_id;
+                self.active_player_id = *pid;
+                for player_id in &self.teams[team_id].player_ids {
+                    self.players[*player_id].lands_played = 0;
+                }
+                Ok(())
+            }
+            Message::GetPriority(pid) => {
+                self.player_mut(*pid)?;
+                self.priority_player_id = *pid;
+                Ok(())
+            }
+            Message::Seed(seed) => {
+                self.seed = *seed;
+                self.rng = GameRng::seed_from_u64(*seed);
+                Ok(())
+            }
+            Message::ShuffleLibrary(pid, order) => {
+                let mut library = self.player_mut(*pid)?.library.clone();
+                let mut new_library = order.clone();
+                library.sort_unstable();
+                new_library.sort_unstable();
+                if library == new_library {
+                    for card_id in order {
+                        self.assign_public_id(*card_id);
+                    }
+                    self.players[*pid].library = order.clone();
+                    Ok(())
+                } else {
+                    Err(HandleError::LibraryError(*pid))
+                }
+            }
+            Message::DrawCard(pid, cid) => {
+                let player = self.player_mut(*pid)?;
+                if player.library.last() != Some(cid) {
+                    return Err(HandleError::CardIdError(*cid));
+                }
+                player.library.pop();
+                player.hand.push(*cid);
+                self.assign_public_id(*cid);
+                Ok(())
+            }
+            Message::DrawFromEmpty(pid) => {
+                self.player_mut(*pid)?.has_drawn_from_empty = true;
+                Ok(())
+            }
+            Message::PlayerLoses(pid, _) => {
+                self.player_mut(*pid)?.has_lost = true;
+                Ok(())
+            }
+            Message::TeamWins(tid) => {
+                if *tid < self.teams.len() {
+                    Ok(())
+                } else {
+                    Err(HandleError::TeamIdError(*tid))
+                }
+            }
+            Message::PlayerHasPriority(pid) => {
+                self.player_mut(*pid)?;
+                self.priority_player_id = *pid;
+                Ok(())
+            }
+            Message::PlayerPasses(pid) => {
+                self.player_mut(*pid)?.has_passed = true;
+                Ok(())
+            }
+            Message::PriorityEnded => {
+                for p in self.players.iter_mut() {
+                    p.has_passed = false;
+                }
+                Ok(())
+            }
+            Message::ResolveSpell(sid) => match self.stack.last() {
+                Some(spell) if spell.id == *sid => {
+                    self.stack.pop();
+                    Ok(())
+                }
+                _ => Err(HandleError::SpellIdError(*sid)),
+            },
+            Message::Discard(pid, cid) => {
+                let player = self.player_mut(*pid)?;
+                match player.hand.iter().position(|c| *c == *cid) {
+                    Some(i) => {
+                        let card_id = player.hand.remove(i);
+                        player.graveyard.push(card_id);
+                        self.assign_public_id(card_id);
+                        Ok(())
+                    }
+                    None => Err(HandleError::CardIdError(*cid)),
+                }
+            }
+            Message::PlayLand(pid, cid, oid) => {
+                if *oid < self.next_id || self.objects.contains_key(oid) {
+                    return Err(HandleError::ObjectIdError(*oid));
+                }
+                let player = self.player_mut(*pid)?;
+                match player.hand.iter().position(|c| *c == *cid) {
+                    Some(i) => {
+                        let card_id = player.hand.remove(i);
+                        player.lands_played += 1;
+                        self.commit_id(*oid);
+                        let object = Object {
+                            id: *oid,
+                            controller_id: *pid,
+                            kind: ObjectKind::Card(card_id),
+                            location: ObjectLocation::Battlefield,
+                        };
+                        if let Some(card) = self.cards.get_mut(&card_id) {
+                            card.object_id = Some(*oid);
+                        }
+                        self.objects.insert(*oid, object);
+                        self.assign_public_id(card_id);
+                        Ok(())
+                    }
+                    None => Err(HandleError::CardIdError(*cid)),
+                }
+            }
+            Message::AddHiddenCard { id, owner_id } => {
+                // The game has added the card already
+                match self.card_id_from_public(*id) {
+                    Some(card_id) if self.cards[&card_id].owner_id == *owner_id => Ok(()),
+                    _ => Err(HandleError::CardIdError(*id)),
+                }
+            }
+            Message::NewPublicId(_, public_id) => {
+                // The game has changed the id already
+                match self.card_id_from_public(*public_id) {
+                    Some(_) => Ok(()),
+                    None => Err(HandleError::CardIdError(*public_id)),
+                }
+            }
+            Message::Reveal(public_id, _) => {
+                // The game knows all cards already
+                match self.card_id_from_public(*public_id) {
+                    Some(_) => Ok(()),
+                    None => Err(HandleError::CardIdError(*public_id)),
+                }
+            }
+            Message::RemoveObject(oid) => match self.objects.remove(oid) {
+                Some(object) => {
+                    if let ObjectKind::Card(card_id) = object.kind {
+                        if let Some(card) = self.cards.get_mut(&card_id) {
+                            card.object_id = None;
+                        }
+                    }
+                    Ok(())
+                }
+                None => Err(HandleError::ObjectIdError(*oid)),
+            },
+            Message::RemoveFromGame(cid) => {
+                let card = self
+                    .cards
+                    .get_mut(cid)
+                    .ok_or(HandleError::CardIdError(*cid))?;
+                let owner_id = card.owner_id;
+                if let Some(oid) = card.object_id.take() {
+                    self.objects.remove(&oid);
+                } else {
+                    let player = self.player_mut(owner_id)?;
+                    let zone = [&mut player.library, &mut player.hand, &mut player.graveyard]
+                        .into_iter()
+                        .find(|zone| zone.contains(cid))
+                        .ok_or(HandleError::CardIdError(*cid))?;
+                    zone.retain(|c| c != cid);
+                }
+                self.player_mut(owner_id)?.removed.push(*cid);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Why a player has lost the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LossReason {
+    /// the life of their team dropped to 0 or less
+    Life,
+    /// they or a teammate had to draw from an empty library
+    EmptyLibrary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Substep {
+    InitialShuffle,
+    InitialDrawCards,
+    BeginOfStep,
+    CheckStateBasedActions,
+    CheckTriggers,
+    PlayerPriority,
+    ResolveStack,
+    EndOfStep,
+    GameEnded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Step {
+    Untap,
+    Upkeep,
+    Draw,
+    PrecombatMain,
+    BeginCombat,
+    DeclareAttackers,
+    DeclareBlockers,
+    FirstStrikeDamage,
+    SecondStrikeDamage,
+    EndOfCombat,
+    PostcombatMain,
+    End,
+    Cleanup,
+}
+
+fn try_draw_cards(player: &Player, count: usize) -> Vec<Message> {
+    let mut msg = Vec::new();
+    let n = player.library.len();
+    for i in 0..count {
+        if i < n {
+            let card_id = player.library[n - 1 - i];
+            msg.push(Message::DrawCard(player.id, card_id));
+        } else {
+            msg.push(Message::DrawFromEmpty(player.id));
+            break;
+        }
+    }
+    msg
+}
+
+fn try_draw_card(player: &Player) -> Message {
+    match player.library.last() {
+        Some(card_id) => Message::DrawCard(player.id, *card_id),
+        None => Message::DrawFromEmpty(player.id),
+    }
+}
+
+fn state_based_actions(game: &Game) -> Vec<Message> {
+    let mut msg = Vec::new();
+    let mut losses = Vec::new();
+    let mut losing_teams = Vec::new();
+    for team in game.remaining_teams() {
+        let members = team.player_ids.iter().map(|pid| &game.players[*pid]);
+        let reason = if team.life <= 0 {
+            Some(LossReason::Life)
+        } else if members.clone().any(|p| p.has_drawn_from_empty) {
+            Some(LossReason::EmptyLibrary)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            losing_teams.push(team.id);
+            // the whole team loses together
+            for player in members.filter(|p| !p.has_lost) {
+                losses.push(Message::PlayerLoses(player.id, reason));
+            }
+        }
+    }
+    // the players leave the game with all objects they own or control, an object
+    // owned by one losing team and controlled by another leaves only once
+    let has_lost = |pid: PlayerID| losing_teams.contains(&game.players[pid].team_id);
+    let mut doomed = BTreeSet::new();
+    for object in game.objects.values() {
+        let owner_id = match object.kind {
+            ObjectKind::Card(cid) => game.cards[&cid].owner_id,
+            ObjectKind::ActivatedAbility(_, _) => object.controller_id,
+        };
+        if has_lost(object.controller_id) || has_lost(owner_id) {
+            doomed.insert(object.id);
+        }
+    }
+    for oid in doomed {
+        losses.push(match game.objects[&oid].kind {
+            ObjectKind::Card(cid) => Message::RemoveFromGame(cid),
+            ObjectKind::ActivatedAbility(_, _) => Message::RemoveObject(oid),
+        });
+    }
+    for team_id in &losing_teams {
+        for pid in &game.teams[*team_id].player_ids {
+            let player = &game.players[*pid];
+            let zones = player.library.iter().chain(&player.hand);
+            for cid in zones.chain(&player.graveyard) {
+                losses.push(Message::RemoveFromGame(*cid));
+            }
+        }
+    }
+    // a player who lost can not hold priority, it goes beforehand to the next player
+    // still in the game
+    let n = game.players.len();
+    let in_game = |pid: &PlayerID| !game.players[*pid].has_lost && !has_lost(*pid);
+    if n > 0 && !in_game(&game.priority_player_id) {
+        let next = (1..n)
+            .map(|i| (game.priority_player_id + i) % n)
+            .find(in_game);
+        if let Some(pid) = next {
+            msg.push(Message::PlayerHasPriority(pid));
+        }
+    }
+    msg.extend(losses);
+    // TODO: put all creatures whose damage exceeds their toughness into the graveyard
+    // TODO: put unattached enchantements into the graveyard
+    // Note: Contrary to magic the gathering winning is also a state based action
+    if let Some(winner) = game.winning_team() {
+        msg.push(Message::TeamWins(winner));
+        msg.push(Message::Substep(Substep::GameEnded));
+    } else if game.remaining_teams().next().is_none() {
+        // all have lost
+        msg.push(Message::Substep(Substep::GameEnded));
+    }
+    msg
+}
+
+fn start_player_priority(game: &Game) -> Vec<Message> {
+    let mut msg = Vec::new();
+    assert!(game.players.iter().all(|p| !p.has_passed));
+    msg.push(Message::Substep(Substep::PlayerPriority));
+    msg.push(Message::PlayerHasPriority(
+        game.player_in_game_from(game.active_player_id),
+    ));
+    msg
+}
+
+fn ask_query<'a>(
+    game: &'a Game,
+    msg: &mut Vec<Message>,
+    player_id: PlayerID,
+    query: Query,
+) -> Option<&'a Answer> {
+    match &game.maybe_answer {
+        Some(answer) if validate_answer(&query, answer) => {
+            msg.push(Message::AcceptAnswer);
+            Some(answer)
+        }
+        Some(_) => {
+            msg.push(Message::RejectAnswer);
+            msg.push(Message::Query(player_id, query));
+            None
+        }
+        _ => {
+            msg.push(Message::Query(player_id, query));
+            None
+        }
+    }
+}
+
+/// The messages advancing the game by one substep.
+/// Random events like shuffling are decided here with the random number generator
+/// of the game and the results are part of the messages, so that applying the
+/// messages with `handle_message` does not depend on any hidden state.
+pub fn next_step(game: &mut Game) -> Vec<Message> {
+    let mut msg = Vec::new();
+    match game.substep {
+        Substep::InitialShuffle => {
+            for pid in 0..game.players.len() {
+                let mut library = game.players[pid].library.clone();
+                library.shuffle(&mut game.rng);
+                msg.push(Message::ShuffleLibrary(pid, library));
+            }
+            // after random events the game is reseeded, so that the state of the random
+            // number generator is part of the messages like everything else
+            msg.push(Message::Seed(game.rng.gen()));
+            msg.push(Message::Substep(Substep::InitialDrawCards));
+        }
+        Substep::InitialDrawCards => {
+            for player in &game.players {
+                msg.extend(try_draw_cards(player, 7))
+            }
+            msg.push(Message::Substep(Substep::CheckStateBasedActions));
+        }
+        Substep::CheckStateBasedActions => {
+            let actions = state_based_actions(game);
+            if !actions.is_empty() {
+                msg.extend(actions);
+            } else {
+                msg.extend(start_player_priority(game));
+            }
+        }
+        Substep::PlayerPriority => {
+            let priority_player = &game.players[game.priority_player_id];
+            if priority_player.has_passed || priority_player.has_lost {
+                if game.players.iter().all(|p| p.has_passed || p.has_lost) {
+                    msg.push(Message::PriorityEnded);
+                    msg.push(Message::Substep(Substep::ResolveStack))
+                } else {
+                    msg.push(Message::PlayerHasPriority(
+                        game.next_player_id(game.priority_player_id),
+                    ));
+                }
+            } else {
+                let mut actions = vec![PriorityAction::Pass];
+                if (game.step == Step::PrecombatMain || game.step == Step::PostcombatMain)
+                    && game.is_active_player(game.priority_player_id)
+                    && priority_player.lands_played < 1
+                {
+                    for card_id in priority_player.hand.iter() {
+                        if game.definition(*card_id).mechanics.is_land {
+                            actions.push(PriorityAction::PlayLand(*card_id));
+                        }
+                    }
+                }
+                let query = Query::PriorityAction(actions);
+                if let Some(Answer::PriorityAction(action)) =
+                    ask_query(game, &mut msg, priority_player.id, query)
+                {
+                    match action {
+                        PriorityAction::Pass => msg.push(Message::PlayerPasses(priority_player.id)),
+                        PriorityAction::PlayLand(cid) => msg.push(Message::PlayLand(
+                            game.priority_player_id,
+                            *cid,
+                            game.next_id,
+                        )),
+                    }
+                }
+            }
+        }
+        Substep::ResolveStack => match game.stack.last() {
+            Some(spell) => {
+                msg.push(Message::ResolveSpell(spell.id));
+                msg.extend(spell.resolve());
+            }
+            None => {
+                msg.push(Message::Substep(Substep::EndOfStep));
+            }
+        },
+        Substep::EndOfStep => {
+            // todo: empty mana pool
+
+            use Step::*;
+            // the turn of a player who has lost ends with the current step
+            let turn_ended = game.step == Cleanup || game.players[game.active_player_id].has_lost;
+            if turn_ended {
+                msg.push(Message::BeginTurn(
+                    game.next_team_player_id(game.active_player_id),
+                ));
+            }
+            msg.push(Message::Step(match game.step {
+                _ if turn_ended => Untap,
+                Untap => Upkeep,
+                Upkeep => Draw,
+                Draw => PrecombatMain,
+                PrecombatMain => BeginCombat,
+                BeginCombat => DeclareAttackers,
+                DeclareAttackers => {
+                    // TODO: let the active player choose which opponent each creature
+                    // attacks once the engine has creatures
+                    if false
+                    /* attackers declared ? */
+                    {
+                        DeclareBlockers
+                    } else {
+                        EndOfCombat
+                    }
+                }
+                DeclareBlockers => FirstStrikeDamage,
+                FirstStrikeDamage => SecondStrikeDamage,
+                SecondStrikeDamage => EndOfCombat,
+                EndOfCombat => PostcombatMain,
+                PostcombatMain => End,
+                End => Cleanup,
+                Cleanup => Untap,
+            }));
+            msg.push(Message::Substep(Substep::BeginOfStep));
+        }
+        Substep::BeginOfStep => {
+            match game.step {
+                Step::Untap => {
+                    // Untap all permanents controlled by active player
+                    msg.push(Message::Substep(Substep::CheckStateBasedActions));
+                }
+                Step::Draw => {
+                    for player in game.players.iter() {
+                        if game.is_active_player(player.id) && !player.has_lost {
+                            msg.extend(try_draw_cards(player, 1));
+                        }
+                    }
+                    msg.push(Message::Substep(Substep::CheckStateBasedActions));
+                }
+                Step::Cleanup => {
+                    // active players discard one after the other, this substep is
+                    // repeated until no active player has to discard any more
+                    let discarding_player = game.players.iter().find(|p| {
+                        game.is_active_player(p.id)
+                            && !p.has_lost
+                            && p.hand.len() as i32 > p.max_hand_size()
+                    });
+                    if let Some(player) = discarding_player {
+                        let number_to_discard = player.hand.len() as i32 - player.max_hand_size();
+                        let query = Query::Discard(player.hand.clone(), number_to_discard);
+                        if let Some(Answer::Discard(card_ids)) =
+                            ask_query(game, &mut msg, player.id, query)
+                        {
+                            for card_id in card_ids {
+                                msg.push(Message::Discard(player.id, *card_id));
+                            }
+                        }
+                    } else {
+                        // all damage is removed
+                        // until end of turn ends
+                        msg.push(Message::Substep(Substep::EndOfStep));
+                    }
+                }
+                _ => msg.push(Message::Substep(Substep::CheckStateBasedActions)),
+            }
+        }
+        _ => {
+            msg.push(Message::Substep(Substep::GameEnded));
+        }
+    };
+    msg
+}
+
+/// Maximum number of players at the table
+pub const MAX_PLAYERS: usize = 6;
+
+#[allow(clippy::too_many_arguments)]
+pub fn duel(
+    user1: User,
+    deck1: card::Deck,
+    user2: User,
+    deck2: card::Deck,
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut Vec<Box<dyn MessageConsumer>>,
+    card_repository: Arc<card::CardRepository>,
+    seed: u64,
+) -> Result<Game, HandleError> {
+    multiplayer_game(
+        vec![(user1, deck1), (user2, deck2)],
+        agents,
+        consumers,
+        card_repository,
+        seed,
+    )
+}
+
+/// Play a free-for-all game in which every player is the opponent of all others.
+/// Players take turns in the order they are given, the last player standing wins.
+/// There is no combat yet: without creatures the declare attackers step is skipped,
+/// so no player attacks any opponent.
+pub fn multiplayer_game(
+    players: Vec<(User, card::Deck)>,
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut Vec<Box<dyn MessageConsumer>>,
+    card_repository: Arc<card::CardRepository>,
+    seed: u64,
+) -> Result<Game, HandleError> {
+    team_game(
+        players.into_iter().map(|player| vec![player]).collect(),
+        agents,
+        consumers,
+        card_repository,
+        seed,
+    )
+}
+
+/// Play a game between teams, e.g. two-headed giant.
+/// Teammates share their life total and their turns, the first player of a team
+/// is the first to receive priority during the team's turns.
+/// The game is won by the last team standing.
+/// The queries of each player are answered by the agent at the same seat in `agents`,
+/// which only gets to see that player's view of the game.
+/// All random events of the game are determined by `seed`.
+/// The game stops with an error if one of its messages can not be applied.
+pub fn team_game(
+    teams: Vec<Vec<(User, card::Deck)>>,
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut Vec<Box<dyn MessageConsumer>>,
+    card_repository: Arc<card::CardRepository>,
+    seed: u64,
+) -> Result<Game, HandleError> {
+    start_game(teams, None, agents, consumers, card_repository, seed)
+}
+
+/// Play a game between teams, in which `first_team` takes the first turn,
+/// or a team chosen by a coin flip if it is `None`
+fn start_game(
+    teams: Vec<Vec<(User, card::Deck)>>,
+    first_team: Option<TeamID>,
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut Vec<Box<dyn MessageConsumer>>,
+    card_repository: Arc<card::CardRepository>,
+    seed: u64,
+) -> Result<Game, HandleError> {
+    let nb_players: usize = teams.iter().map(|team| team.len()).sum();
+    assert!(
+        teams.len() >= 2 && teams.iter().all(|team| !team.is_empty()),
+        "a game needs at least two teams with at least one player each"
+    );
+    assert!(
+        nb_players <= MAX_PLAYERS,
+        "a game can have at most {} players",
+        MAX_PLAYERS
+    );
+    assert_eq!(agents.len(), nb_players, "every player needs an agent");
+    let mut views: Vec<_> = (0..nb_players)
+        .map(|player_id| PlayerView::new(player_id, card_repository.clone()))
+        .collect();
+    let mut game = Game::new(card_repository);
+    dispatch(&mut game, &mut views, consumers, &Message::Seed(seed))?;
+
+    let nb_teams = teams.len();
+    let mut decks = Vec::new();
+    for (team_id, team) in teams.into_iter().enumerate() {
+        let msg = Message::CreateTeam {
+            id: team_id,
+            life: Team::starting_life(team.len()),
+        };
+        dispatch(&mut game, &mut views, consumers, &msg)?;
+        for (user, deck) in team {
+            let msg = Message::CreatePlayer {
+                id: decks.len(),
+                name: user.name,
+                team_id,
+            };
+            dispatch(&mut game, &mut views, consumers, &msg)?;
+            decks.push(deck);
+        }
+    }
+
+    for (player_id, deck) in decks.into_iter().enumerate() {
+        for (def_id, count) in deck.0 {
+            for _ in 0..count {
+                let msg = Message::AddCard {
+                    id: game.get_id(),
+                    owner_id: player_id,
+                    def_id,
+                };
+                dispatch(&mut game, &mut views, consumers, &msg)?;
+            }
+        }
+    }
+
+    // unless it is given, flip a coin to decide which team takes the first turn
+    let first_team = match first_team {
+        Some(team_id) => {
+            assert!(
+                team_id < nb_teams,
+                "the first team must be one of the teams"
+            );
+            team_id
+        }
+        None => game.rng.gen_range(0..nb_teams),
+    };
+    let first_player = game.teams[first_team].player_ids[0];
+    let seed = game.rng.gen();
+    dispatch(&mut game, &mut views, consumers, &Message::Seed(seed))?;
+    dispatch(
+        &mut game,
+        &mut views,
+        consumers,
+        &Message::BeginTurn(first_player),
+    )?;
+    play(game, views, agents, consumers)
+}
+
+/// Continue a game in progress, e.g. one loaded with `save::load_game`, until it ends.
+/// The views of the players are made from the state of the game.
+pub fn resume_game(
+    game: Game,
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut [Box<dyn MessageConsumer>],
+) -> Result<Game, HandleError> {
+    assert_eq!(
+        agents.len(),
+        game.players.len(),
+        "every player needs an agent"
+    );
+    let views = (0..game.players.len())
+        .map(|player_id| PlayerView::from_game(&game, player_id))
+        .collect();
+    play(game, views, agents, consumers)
+}
+
+/// Advance the game step by step until it ends, asking the agents to answer the queries
+fn play(
+    mut game: Game,
+    mut views: Vec<PlayerView>,
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut [Box<dyn MessageConsumer>],
+) -> Result<Game, HandleError> {
+    // copies of the game and the views to take answers back are only worth their cost
+    // if an agent is going to ask for it
+    if agents.iter().any(|agent| agent.may_undo()) {
+        dispatch(
+            &mut game,
+            &mut views,
+            consumers,
+            &Message::UndoLimit(MAX_UNDO),
+        )?;
+    }
+    while game.substep != Substep::GameEnded {
+        for msg in next_step(&mut game) {
+            dispatch(&mut game, &mut views, consumers, &msg)?;
+            if let Message::Query(player_id, query) = &msg {
+                let query = filter::public_query(&game, query);
+                let answer = agents[*player_id].answer(&views[*player_id], &query);
+                match (answer, game.answers_to_undo(*player_id)) {
+                    (Answer::Undo, Some(n)) => {
+                        // whether or not the undo is allowed, the next step asks a query again
+                        if undo_allowed(&mut game, &mut views, agents, consumers, *player_id)? {
+                            dispatch(&mut game, &mut views, consumers, &Message::Rollback(n))?;
+                        }
+                    }
+                    (answer, _) => {
+                        game.maybe_answer = Some(filter::private_answer(&game, &answer));
+                    }
+                }
+            }
+        }
+    }
+    Ok(game)
+}
+
+/// Ask the opponents of `player_id` one after the other whether the player may take back
+/// their last decision, until one of them refuses. An opponent who keeps giving other
+/// answers stops the game.
+fn undo_allowed(
+    game: &mut Game,
+    views: &mut [PlayerView],
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut [Box<dyn MessageConsumer>],
+    player_id: PlayerID,
+) -> Result<bool, HandleError> {
+    let team_id = game.players[player_id].team_id;
+    let opponents: Vec<PlayerID> = game
+        .players
+        .iter()
+        .filter(|p| p.team_id != team_id && !p.has_lost)
+        .map(|p| p.id)
+        .collect();
+    for opponent_id in opponents {
+        let query = Query::AllowUndo(player_id);
+        let mut allowed = None;
+        for _ in 0..MAX_REJECTED_ANSWERS {
+            dispatch(
+                game,
+                views,
+                consumers,
+                &Message::Query(opponent_id, query.clone()),
+            )?;
+            match agents[opponent_id].answer(&views[opponent_id], &query) {
+                Answer::AllowUndo(answer) => {
+                    allowed = Some(answer);
+                    break;
+                }
+                _ => dispatch(game, views, consumers, &Message::RejectAnswer)?,
+            }
+        }
+        let allowed = allowed.ok_or(HandleError::AnswerError(opponent_id))?;
+        dispatch(
+            game,
+            views,
+            consumers,
+            &Message::AllowUndo(opponent_id, allowed),
+        )?;
+        if !allowed {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Apply `msg` to the game, update the view of each player with what they may see of it
+/// and pass it on to the consumers. Errors of the consumers are ignored.
+fn dispatch(
+    game: &mut Game,
+    views: &mut [PlayerView],
+    consumers: &mut [Box<dyn MessageConsumer>],
+    msg: &Message,
+) -> Result<(), HandleError> {
+    game.handle_message(msg)?;
+    // debug builds, and so all tests, check the game after every message
+    #[cfg(debug_assertions)]
+    game.check_invariants()
+        .map_err(|e| HandleError::InvalidMessage(Box::new(msg.clone()), Box::new(e)))?;
+    for view in views.iter_mut() {
+        for msg in filter::redact(game, view.player_id, msg) {
+            view.handle_message(&msg)?;
+        }
+    }
+    for consumer in consumers.iter_mut() {
+        let _ = consumer.handle_message(msg);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::{run, Scenario};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn discarding_the_same_card_twice_is_rejected() {
+        let query = Query::Discard(vec![1001, 1002, 1003], 2);
+        assert!(!validate_answer(&query, &Answer::Discard(vec![1001, 1001])));
+        assert!(validate_answer(&query, &Answer::Discard(vec![1001, 1002])));
+
+        let mut game = Scenario::new(Arc::new(card::load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[101; 9])
+            .library(0, &[101; 3])
+            .library(1, &[101; 3])
+            .active_player(0)
+            .step(Step::Cleanup)
+            .build()
+            .unwrap();
+        let card = game.public_id(game.hand(0)[0]);
+        let messages = run(&mut game, vec![Answer::Discard(vec![card, card])]).unwrap();
+        let n = messages.len();
+        assert!(matches!(messages[n - 2], Message::RejectAnswer));
+        assert!(matches!(
+            messages[n - 1],
+            Message::Query(0, Query::Discard(_, 2))
+        ));
+    }
+
+    #[test]
+    fn the_cards_of_a_player_who_lost_leave_the_game() {
+        let mut game = Scenario::new(Arc::new(card::load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[101; 2])
+            .graveyard(0, &[101])
+            .battlefield(0, &[101])
+            .battlefield(1, &[101; 2])
+            .library(1, &[101; 3])
+            .active_player(0)
+            .step(Step::Draw)
+            .build()
+            .unwrap();
+        // Leo has taken control of one of the lands of Marc
+        let stolen = *game.objects.keys().nth(1).unwrap();
+        game.objects.get_mut(&stolen).unwrap().controller_id = 0;
+        let stolen_card = match game.objects[&stolen].kind {
+            ObjectKind::Card(cid) => cid,
+            _ => unreachable!(),
+        };
+
+        run(&mut game, vec![]).unwrap();
+        assert_eq!(game.substep, Substep::GameEnded);
+        assert!(game.players[0].has_lost);
+        let leo = &game.players[0];
+        assert!(leo.library.is_empty() && leo.hand.is_empty() && leo.graveyard.is_empty());
+        assert_eq!(leo.removed.len(), 4);
+        assert_eq!(game.players[1].removed, vec![stolen_card]);
+        assert_eq!(game.objects.len(), 1);
+        game.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn two_teams_losing_at_once_leave_the_game_together() {
+        let mut game = Scenario::new(Arc::new(card::load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .player("Nina")
+            .battlefield(0, &[101])
+            .battlefield(1, &[101])
+            .library(0, &[101; 3])
+            .library(1, &[101; 3])
+            .library(2, &[101; 3])
+            .life(0, 0)
+            .life(1, 0)
+            .active_player(2)
+            .substep(Substep::CheckStateBasedActions)
+            .build()
+            .unwrap();
+        // Leo has taken control of the land of Marc
+        let stolen = *game.objects.keys().nth(1).unwrap();
+        game.objects.get_mut(&stolen).unwrap().controller_id = 0;
+
+        run(&mut game, vec![]).unwrap();
+        assert!(game.players[0].has_lost && game.players[1].has_lost);
+        assert_eq!(game.winning_team(), Some(2));
+        assert_eq!(game.substep, Substep::GameEnded);
+        assert_eq!(game.players[0].removed.len(), 4);
+        assert_eq!(game.players[1].removed.len(), 4);
+        assert!(game.objects.is_empty());
+        game.check_invariants().unwrap();
+    }
+
+    fn two_headed_giant() -> Scenario {
+        Scenario::new(Arc::new(card::load_cards()))
+            .team(&["Leo", "Marc"])
+            .team(&["Nina", "Otto"])
+            .library(0, &[101; 3])
+            .library(1, &[101; 3])
+            .library(2, &[101; 3])
+            .library(3, &[101; 3])
+    }
+
+    #[test]
+    fn teammates_share_their_turns_in_priority_order() {
+        let mut game = two_headed_giant()
+            .hand(1, &[101])
+            .hand(2, &[101])
+            .active_player(0)
+            .step(Step::PrecombatMain)
+            .build()
+            .unwrap();
+        let pass = || Answer::PriorityAction(PriorityAction::Pass);
+        let messages = run(&mut game, vec![pass(), pass(), pass(), pass()]).unwrap();
+        // (asked player, number of actions) of the queries of the main phase
+        let queries: Vec<_> = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                Message::Query(pid, Query::PriorityAction(actions)) => Some((*pid, actions.len())),
+                _ => None,
+            })
+            .take(4)
+            .collect();
+        // Marc may play a land during the turn of their team, Nina may not
+        assert_eq!(queries, vec![(0, 1), (1, 2), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn teammates_lose_together_when_their_shared_life_is_gone() {
+        let mut game = two_headed_giant()
+            .active_player(0)
+            .substep(Substep::CheckStateBasedActions)
+            .build()
+            .unwrap();
+        assert_eq!((game.life(0), game.life(1)), (30, 30));
+        game.teams[1].life = 0;
+        run(&mut game, vec![]).unwrap();
+        assert!(!game.players[0].has_lost && !game.players[1].has_lost);
+        assert!(game.players[2].has_lost && game.players[3].has_lost);
+        assert_eq!(game.winning_team(), Some(0));
+        assert_eq!(game.substep, Substep::GameEnded);
+    }
+
+    #[test]
+    fn a_shuffle_records_the_order_of_the_library() {
+        let mut game = Scenario::new(Arc::new(card::load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .library(0, &[101; 10])
+            .library(1, &[101; 10])
+            .substep(Substep::InitialShuffle)
+            .seed(5)
+            .build()
+            .unwrap();
+        let messages = next_step(&mut game);
+        let mut replayed = game.clone();
+        // the random number generator plays no part in applying the messages
+        replayed.rng = GameRng::seed_from_u64(6);
+        for msg in &messages {
+            game.handle_message(msg).unwrap();
+            replayed.handle_message(msg).unwrap();
+        }
+        for pid in 0..2 {
+            let order = messages.iter().find_map(|msg| match msg {
+                Message::ShuffleLibrary(id, order) if *id == pid => Some(order),
+                _ => None,
+            });
+            assert_eq!(Some(&game.players[pid].library), order);
+            assert_eq!(replayed.players[pid].library, game.players[pid].library);
+        }
+
+        // an order which is not the library of the player is rejected
+        let mut order = game.players[0].library.clone();
+        order[0] = order[1];
+        assert!(matches!(
+            game.apply(&Message::ShuffleLibrary(0, order)),
+            Err(HandleError::LibraryError(0))
+        ));
+    }
+
+    fn duel_at_main_phase() -> Game {
+        Scenario::new(Arc::new(card::load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[101; 2])
+            .library(0, &[101; 3])
+            .library(1, &[101; 3])
+            .active_player(0)
+            .step(Step::PrecombatMain)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn a_copy_of_a_game_is_played_on_another_thread() {
+        let game = duel_at_main_phase();
+        let before = serde_json::to_string(&game).unwrap();
+        let copy = game.clone();
+        assert!(Arc::ptr_eq(&game.card_repository, &copy.card_repository));
+        let played = std::thread::spawn(move || {
+            let mut agents: Vec<Box<dyn PlayerAgent>> = vec![
+                Box::new(agent::HeuristicAgent()),
+                Box::new(agent::HeuristicAgent()),
+            ];
+            resume_game(copy, &mut agents, &mut []).unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(played.substep, Substep::GameEnded);
+        // the copy shares nothing else with the game
+        assert_eq!(serde_json::to_string(&game).unwrap(), before);
+    }
+
+    #[test]
+    fn a_card_in_two_zones_is_rejected() {
+        let mut game = duel_at_main_phase();
+        let card_id = game.players[0].hand[0];
+        game.players[0].graveyard.push(card_id);
+        assert!(matches!(
+            game.check_invariants(),
+            Err(HandleError::CardIdError(id)) if id == card_id
+        ));
+    }
+
+    #[test]
+    fn a_lost_player_holding_priority_is_rejected() {
+        let mut game = duel_at_main_phase();
+        game.players[0].has_lost = true;
+        assert!(matches!(
+            game.check_invariants(),
+            Err(HandleError::PlayerIdError(0))
+        ));
+        // unless no player is left in the game
+        game.players[1].has_lost = true;
+        game.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn priority_leaves_a_player_who_lost() {
+        let mut game = Scenario::new(Arc::new(card::load_cards()))
+            .team(&["Leo", "Marc"])
+            .player("Nina")
+            .player("Otto")
+            .library(0, &[101; 3])
+            .library(1, &[101; 3])
+            .library(2, &[101; 3])
+            .library(3, &[101; 3])
+            .life(1, 0)
+            .active_player(0)
+            .priority_player(2)
+            .substep(Substep::CheckStateBasedActions)
+            .build()
+            .unwrap();
+        let messages = run(&mut game, vec![]).unwrap();
+        assert!(matches!(messages[0], Message::PlayerHasPriority(3)));
+        assert!(matches!(
+            messages[1],
+            Message::PlayerLoses(2, LossReason::Life)
+        ));
+        assert!(!game.players[game.priority_player_id].has_lost);
+    }
+
+    #[test]
+    fn the_invariant_checker_rejects_a_broken_game() {
+        let mut checker = InvariantChecker::new(Arc::new(card::load_cards()));
+        let messages = [
+            Message::CreateTeam { id: 0, life: 20 },
+            Message::CreatePlayer {
+                id: 0,
+                name: "Leo".to_string(),
+                team_id: 0,
+            },
+            Message::AddCard {
+                id: 1001,
+                owner_id: 0,
+                def_id: 101,
+            },
+        ];
+        for msg in &messages {
+            checker.handle_message(msg).unwrap();
+        }
+        checker.0.players[0].hand.push(1001);
+        assert!(matches!(
+            checker.handle_message(&Message::Step(Step::Upkeep)),
+            Err(HandleError::InvalidMessage(_, e)) if matches!(*e, HandleError::CardIdError(1001))
+        ));
+    }
+
+    #[test]
+    fn answers_can_only_be_taken_back_once_enabled() {
+        let pass = || Answer::PriorityAction(PriorityAction::Pass);
+        let mut game = duel_at_main_phase();
+        run(&mut game, vec![pass()]).unwrap();
+        assert!(game.undo.is_empty());
+        assert!(game.handle_message(&Message::Rollback(1)).is_err());
+
+        let mut game = duel_at_main_phase();
+        game.handle_message(&Message::UndoLimit(MAX_UNDO)).unwrap();
+        run(&mut game, vec![pass()]).unwrap();
+        assert_eq!(game.undo.len(), 1);
+        game.handle_message(&Message::Rollback(1)).unwrap();
+        assert_eq!(game.priority_player_id, 0);
+        assert!(!game.players[0].has_passed);
+    }
+
+    /// Plays a land, asks once to take it back and passes from then on
+    struct TakesBackALand(usize);
+
+    /// Answers whether an answer may be taken back with `Some(allowed)`,
+    /// or with a priority action instead if it is `None`, and passes otherwise
+    struct Opponent(Option<bool>);
+
+    fn pass_or_discard(query: &Query) -> Answer {
+        match query {
+            Query::Discard(cards, n) => Answer::Discard(cards[..*n as usize].to_vec()),
+            _ => Answer::PriorityAction(PriorityAction::Pass),
+        }
+    }
+
+    impl PlayerAgent for TakesBackALand {
+        fn answer(&mut self, _view: &PlayerView, query: &Query) -> Answer {
+            self.0 += 1;
+            match (self.0, query) {
+                (1, Query::PriorityAction(actions)) => Answer::PriorityAction(actions[1].clone()),
+                (2, _) => Answer::Undo,
+                _ => pass_or_discard(query),
+            }
+        }
+
+        fn may_undo(&self) -> bool {
+            true
+        }
+    }
+
+    impl PlayerAgent for Opponent {
+        fn answer(&mut self, _view: &PlayerView, query: &Query) -> Answer {
+            match (query, self.0) {
+                (Query::AllowUndo(_), Some(allowed)) => Answer::AllowUndo(allowed),
+                _ => pass_or_discard(query),
+            }
+        }
+    }
+
+    struct Collector(Rc<RefCell<Vec<Message>>>);
+
+    impl MessageConsumer for Collector {
+        fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+            self.0.borrow_mut().push(msg.clone());
+            Ok(())
+        }
+    }
+
+    /// The messages of a duel between random agents, as JSON
+    fn duel_messages(seed: u64) -> String {
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let mut consumers: Vec<Box<dyn MessageConsumer>> =
+            vec![Box::new(Collector(messages.clone()))];
+        let mut agents: Vec<Box<dyn PlayerAgent>> = vec![
+            Box::new(agent::RandomAgent::new(seed)),
+            Box::new(agent::RandomAgent::new(seed + 1)),
+        ];
+        let user = |name: &str| User {
+            name: name.to_string(),
+        };
+        duel(
+            user("Leo"),
+            card::Deck(vec![(101, 12)]),
+            user("Marc"),
+            card::Deck(vec![(101, 12)]),
+            &mut agents,
+            &mut consumers,
+            Arc::new(card::load_cards()),
+            seed,
+        )
+        .unwrap();
+        drop(consumers);
+        let messages = messages.take();
+        assert!(matches!(messages[0], Message::Seed(s) if s == seed));
+        serde_json::to_string(&messages).unwrap()
+    }
+
+    #[test]
+    fn the_same_seed_plays_the_same_game() {
+        assert_eq!(duel_messages(11), duel_messages(11));
+        assert_ne!(duel_messages(11), duel_messages(12));
+    }
+
+    /// Play the duel of `duel_at_main_phase` in which Leo takes back a land
+    fn play_with_undo(allowed: Option<bool>) -> (Result<Game, HandleError>, Vec<Message>) {
+        let game = duel_at_main_phase();
+        let views = (0..2)
+            .map(|pid| PlayerView::from_game(&game, pid))
+            .collect();
+        let mut agents: Vec<Box<dyn PlayerAgent>> =
+            vec![Box::new(TakesBackALand(0)), Box::new(Opponent(allowed))];
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let mut consumers: Vec<Box<dyn MessageConsumer>> =
+            vec![Box::new(Collector(messages.clone()))];
+        let result = play(game, views, &mut agents, &mut consumers);
+        drop(consumers);
+        (result, messages.take())
+    }
+
+    #[test]
+    fn an_answer_is_taken_back_when_the_opponent_allows_it() {
+        let (result, messages) = play_with_undo(Some(true));
+        assert_eq!(result.unwrap().substep, Substep::GameEnded);
+        let allowed = messages
+            .iter()
+            .position(|msg| matches!(msg, Message::AllowUndo(1, true)))
+            .unwrap();
+        assert!(matches!(messages[allowed + 1], Message::Rollback(_)));
+        // Leo can play either land again
+        let query = messages[allowed..]
+            .iter()
+            .find(|msg| matches!(msg, Message::Query(0, _)));
+        assert!(matches!(
+            query,
+            Some(Message::Query(0, Query::PriorityAction(actions))) if actions.len() == 3
+        ));
+    }
+
+    #[test]
+    fn an_answer_is_kept_when_the_opponent_refuses_to_take_it_back() {
+        let (result, messages) = play_with_undo(Some(false));
+        assert_eq!(result.unwrap().substep, Substep::GameEnded);
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::AllowUndo(1, false))));
+        assert!(!messages
+            .iter()
+            .any(|msg| matches!(msg, Message::Rollback(_))));
+    }
+
+    #[test]
+    fn an_opponent_who_does_not_answer_whether_to_take_back_stops_the_game() {
+        let (result, messages) = play_with_undo(None);
+        assert!(matches!(result, Err(HandleError::AnswerError(1))));
+        let rejected = messages
+            .iter()
+            .filter(|msg| matches!(msg, Message::RejectAnswer))
+            .count();
+        assert_eq!(rejected, MAX_REJECTED_ANSWERS);
+    }
+}
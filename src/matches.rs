@@ -0,0 +1,298 @@
+//! Matches of several games between the same two players, as played in tournaments.
+//!
+//! The first game of a match begins with a coin flip. Before each later game the loser
+//! of the previous game, or the player who went second if it was a draw, chooses
+//! whether to play or draw, and then both players may swap cards between their main
+//! deck and their sideboard. These queries are messages of the game which has just
+//! ended, sent after its end, so that the consumers see them and e.g. a server passes
+//! them on to its clients. The agents answer them with their view of that game.
+use crate::agent::PlayerAgent;
+use crate::card::{CardDefID, CardRepository, Deck};
+use crate::view::PlayerView;
+use crate::{
+    dispatch, start_game, validate_answer, Answer, Game, GameRng, HandleError, Message,
+    MessageConsumer, PlayerID, Query, User, MAX_REJECTED_ANSWERS,
+};
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The results of the games of a match so far
+#[derive(Debug, Clone, Default)]
+pub struct MatchRecord {
+    /// games won by each player
+    pub wins: [u32; 2],
+    pub draws: u32,
+    /// the winner of every game in order, `None` for a draw
+    pub games: Vec<Option<PlayerID>>,
+    /// the player who took the first turn of every game
+    pub first_players: Vec<PlayerID>,
+}
+
+impl MatchRecord {
+    /// The player who won more games, `None` if both won as many
+    pub fn winner(&self) -> Option<PlayerID> {
+        match self.wins[0].cmp(&self.wins[1]) {
+            std::cmp::Ordering::Greater => Some(0),
+            std::cmp::Ordering::Less => Some(1),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+pub struct Match {
+    users: [User; 2],
+    decks: [Deck; 2],
+    sideboards: [Deck; 2],
+    /// the match is over once a player has won this many games
+    games_to_win: u32,
+    /// or this many games have been played, some being drawn
+    max_games: u32,
+    sideboarding: bool,
+    record: MatchRecord,
+}
+
+impl Match {
+    /// A match won by the first player to win two games, with a third game at most.
+    /// Each player is given with their main deck and their sideboard.
+    pub fn best_of_three(player1: (User, Deck, Deck), player2: (User, Deck, Deck)) -> Self {
+        let (user1, deck1, sideboard1) = player1;
+        let (user2, deck2, sideboard2) = player2;
+        Match {
+            users: [user1, user2],
+            decks: [deck1, deck2],
+            sideboards: [sideboard1, sideboard2],
+            games_to_win: 2,
+            max_games: 3,
+            sideboarding: true,
+            record: MatchRecord::default(),
+        }
+    }
+
+    /// Play all games with the main decks the match started with
+    pub fn without_sideboarding(mut self) -> Self {
+        self.sideboarding = false;
+        self
+    }
+
+    pub fn record(&self) -> &MatchRecord {
+        &self.record
+    }
+
+    /// The current main deck of a player
+    pub fn deck(&self, player_id: PlayerID) -> &Deck {
+        &self.decks[player_id]
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.record
+            .wins
+            .iter()
+            .any(|wins| *wins >= self.games_to_win)
+            || self.record.games.len() as u32 >= self.max_games
+    }
+
+    /// Play the games of the match until it is over. The players keep their seats,
+    /// and so their agents, in every game; the consumers get the messages of all games.
+    /// All random events of the match are determined by `seed`.
+    pub fn play(
+        &mut self,
+        agents: &mut [Box<dyn PlayerAgent>],
+        consumers: &mut Vec<Box<dyn MessageConsumer>>,
+        card_repository: Arc<CardRepository>,
+        seed: u64,
+    ) -> Result<&MatchRecord, HandleError> {
+        assert_eq!(agents.len(), 2, "every player needs an agent");
+        let mut rng = GameRng::seed_from_u64(seed);
+        // flip a coin to decide who takes the first turn of the first game
+        let mut first_player = rng.gen_range(0..2);
+        while !self.is_over() {
+            let teams = (0..2)
+                .map(|pid| vec![(self.users[pid].clone(), self.decks[pid].clone())])
+                .collect();
+            // in a duel every team has one player, with the same id
+            let mut game = start_game(
+                teams,
+                Some(first_player),
+                agents,
+                consumers,
+                card_repository.clone(),
+                rng.gen(),
+            )?;
+            let winner = game.winning_team();
+            match winner {
+                Some(pid) => self.record.wins[pid] += 1,
+                None => self.record.draws += 1,
+            }
+            self.record.games.push(winner);
+            self.record.first_players.push(first_player);
+            if self.is_over() {
+                break;
+            }
+
+            let chooser = match winner {
+                Some(pid) => 1 - pid,
+                None => 1 - first_player,
+            };
+            first_player = match ask(&mut game, chooser, agents, consumers, Query::PlayFirst)? {
+                Answer::PlayFirst(true) => chooser,
+                _ => 1 - chooser,
+            };
+            if self.sideboarding {
+                for pid in 0..2 {
+                    if self.sideboards[pid].0.is_empty() {
+                        continue;
+                    }
+                    let query =
+                        Query::Sideboard(self.decks[pid].0.clone(), self.sideboards[pid].0.clone());
+                    if let Answer::Sideboard(main) = ask(&mut game, pid, agents, consumers, query)?
+                    {
+                        self.sideboards[pid] =
+                            remaining_cards(&self.decks[pid], &self.sideboards[pid], &main);
+                        self.decks[pid] = Deck(main);
+                    }
+                }
+            }
+        }
+        Ok(&self.record)
+    }
+}
+
+/// Ask a query between games with the messages of the game which has just ended.
+/// Like in a game an invalid answer is rejected and the query asked again, but only
+/// so many times.
+fn ask(
+    game: &mut Game,
+    player_id: PlayerID,
+    agents: &mut [Box<dyn PlayerAgent>],
+    consumers: &mut [Box<dyn MessageConsumer>],
+    query: Query,
+) -> Result<Answer, HandleError> {
+    let mut views: Vec<_> = (0..game.players.len())
+        .map(|pid| PlayerView::from_game(game, pid))
+        .collect();
+    for _ in 0..MAX_REJECTED_ANSWERS {
+        let msg = Message::Query(player_id, query.clone());
+        dispatch(game, &mut views, consumers, &msg)?;
+        let answer = agents[player_id].answer(&views[player_id], &query);
+        if validate_answer(&query, &answer) {
+            dispatch(game, &mut views, consumers, &Message::AcceptAnswer)?;
+            return Ok(answer);
+        }
+        dispatch(game, &mut views, consumers, &Message::RejectAnswer)?;
+    }
+    Err(HandleError::AnswerError(player_id))
+}
+
+/// The cards of `deck` and `sideboard` which are not part of the new main deck `main`
+fn remaining_cards(deck: &Deck, sideboard: &Deck, main: &[(CardDefID, usize)]) -> Deck {
+    let mut counts: BTreeMap<CardDefID, usize> = BTreeMap::new();
+    for (def_id, count) in deck.0.iter().chain(&sideboard.0) {
+        *counts.entry(*def_id).or_default() += count;
+    }
+    for (def_id, count) in main {
+        if let Some(total) = counts.get_mut(def_id) {
+            *total -= count;
+        }
+    }
+    Deck(counts.into_iter().filter(|(_, count)| *count > 0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::HeuristicAgent;
+    use crate::card::load_cards;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Plays like the heuristic agent, but always chooses to draw first
+    /// and answers the sideboard query with an invalid deck if `cheats`
+    struct Drawing {
+        cheats: bool,
+    }
+
+    impl PlayerAgent for Drawing {
+        fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+            match query {
+                Query::PlayFirst => Answer::PlayFirst(false),
+                Query::Sideboard(_, _) if self.cheats => Answer::Sideboard(vec![(101, 100)]),
+                _ => HeuristicAgent().answer(view, query),
+            }
+        }
+    }
+
+    /// Counts the queries between the games and their rejected answers
+    struct Counter(Rc<RefCell<(usize, usize)>>);
+
+    impl MessageConsumer for Counter {
+        fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+            let mut counts = self.0.borrow_mut();
+            match msg {
+                Message::Query(_, Query::PlayFirst | Query::Sideboard(_, _)) => counts.0 += 1,
+                Message::RejectAnswer => counts.1 += 1,
+                _ => {}
+            }
+            Ok(())
+        }
+    }
+
+    fn player(name: &str, sideboard: usize) -> (User, Deck, Deck) {
+        let user = User {
+            name: name.to_string(),
+        };
+        let sideboard = match sideboard {
+            0 => Vec::new(),
+            n => vec![(101, n)],
+        };
+        (user, Deck(vec![(101, 12)]), Deck(sideboard))
+    }
+
+    fn play(
+        cheats: bool,
+        sideboard: usize,
+    ) -> (Match, Result<MatchRecord, HandleError>, usize, usize) {
+        let mut agents: Vec<Box<dyn PlayerAgent>> = vec![
+            Box::new(Drawing { cheats }),
+            Box::new(Drawing { cheats: false }),
+        ];
+        let counts = Rc::new(RefCell::new((0, 0)));
+        let mut consumers: Vec<Box<dyn MessageConsumer>> = vec![Box::new(Counter(counts.clone()))];
+        let mut the_match = Match::best_of_three(player("Leo", sideboard), player("Marc", 0));
+        let result = the_match
+            .play(&mut agents, &mut consumers, Arc::new(load_cards()), 5)
+            .cloned();
+        let (queries, rejected) = *counts.borrow();
+        (the_match, result, queries, rejected)
+    }
+
+    #[test]
+    fn the_loser_chooses_to_draw() {
+        let (the_match, result, queries, rejected) = play(false, 0);
+        let record = result.unwrap();
+        assert!(the_match.is_over());
+        assert_eq!(
+            record.wins.iter().sum::<u32>() + record.draws,
+            record.games.len() as u32
+        );
+        assert_eq!(record.first_players.len(), record.games.len());
+        assert!(record.wins.contains(&2));
+        // the loser of each game lets the winner take the first turn of the next one
+        for i in 1..record.games.len() {
+            let winner = record.games[i - 1].expect("no game of the match is drawn");
+            assert_eq!(record.first_players[i], winner);
+        }
+        // only the play or draw choice is asked, as there are no sideboards
+        assert_eq!(queries, record.games.len() - 1);
+        assert_eq!(rejected, 0);
+    }
+
+    #[test]
+    fn a_player_giving_invalid_answers_stops_the_match() {
+        let (the_match, result, queries, rejected) = play(true, 2);
+        assert!(matches!(result, Err(HandleError::AnswerError(0))));
+        assert_eq!(the_match.record().games.len(), 1);
+        assert_eq!(rejected, MAX_REJECTED_ANSWERS);
+        assert_eq!(queries, MAX_REJECTED_ANSWERS + 1);
+    }
+}
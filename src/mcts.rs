@@ -0,0 +1,424 @@
+use crate::agent::{random_answer, HeuristicAgent, PlayerAgent};
+use crate::view::PlayerView;
+use crate::{
+    next_step, Answer, Game, GameRng, HandleError, Message, MessageConsumer, PlayerID, Query,
+    Substep,
+};
+use rand::seq::{index, SliceRandom};
+use rand::SeedableRng;
+use std::time::{Duration, Instant};
+
+/// Settings of the Monte Carlo tree search
+#[derive(Debug, Clone)]
+pub struct MctsConfig {
+    /// Maximal number of playouts per query
+    pub iterations: usize,
+    /// The search stops after this time even if not all iterations are done
+    pub time_budget: Duration,
+    /// Weight of the exploration term in the UCB formula
+    pub exploration: f64,
+    /// Use the heuristic agent instead of random answers during playouts
+    pub heuristic_playouts: bool,
+    /// Playouts are scored as a draw when the game takes more steps than this
+    pub max_playout_steps: usize,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            iterations: 1000,
+            time_budget: Duration::from_secs(1),
+            exploration: 0.7,
+            heuristic_playouts: false,
+            max_playout_steps: 10_000,
+        }
+    }
+}
+
+impl MctsConfig {
+    /// A cheaper search for playing many games, as simulations and tournaments do
+    pub fn batch(iterations: usize) -> Self {
+        MctsConfig {
+            iterations,
+            time_budget: Duration::from_millis(100),
+            ..MctsConfig::default()
+        }
+    }
+}
+
+/// Default number of playouts per query when many games are played
+pub const BATCH_ITERATIONS: usize = 100;
+
+/// An agent using information set Monte Carlo tree search.
+/// For every playout the hidden cards of the player's view, i.e. the libraries
+/// and the hands of the other players, are replaced by a random guess which is
+/// consistent with what the player knows. All playouts share a single tree
+/// in which the queries of all players are nodes.
+pub struct MctsAgent {
+    config: MctsConfig,
+    rng: GameRng,
+}
+
+/// Number of alternatives considered for a query with many possible answers
+const MAX_CANDIDATES: usize = 20;
+
+/// A node of the search tree, reached by giving `answer` to the query of the parent
+struct Node {
+    answer: Option<Answer>,
+    /// player who gave the answer leading to this node
+    player_id: PlayerID,
+    visits: u32,
+    /// number of times this node could have been chosen
+    availability: u32,
+    /// sum of the playout results from the point of view of `player_id`
+    reward: f64,
+    children: Vec<usize>,
+}
+
+impl MctsAgent {
+    pub fn new(config: MctsConfig, seed: u64) -> Self {
+        MctsAgent {
+            config,
+            rng: GameRng::seed_from_u64(seed),
+        }
+    }
+
+    /// One playout in a fresh determinization of `view`, updating the tree in `nodes`.
+    /// A playout producing a message the game rejects is not scored and leaves the
+    /// tree as it was.
+    fn iterate(&mut self, nodes: &mut Vec<Node>, view: &PlayerView) -> Result<(), HandleError> {
+        let mut game = view.determinize(&mut self.rng);
+        let mut path = vec![0];
+        let nb_nodes = nodes.len();
+        if let Err(e) = self.playout(nodes, &mut path, &mut game) {
+            // a playout adds at most one node, the last of its path
+            if nodes.len() > nb_nodes {
+                nodes.pop();
+                nodes[path[path.len() - 2]].children.pop();
+            }
+            return Err(e);
+        }
+
+        let winner = game.winning_team();
+        for node_id in path {
+            let node = &mut nodes[node_id];
+            node.visits += 1;
+            node.reward += match winner {
+                Some(team_id) if team_id == game.players[node.player_id].team_id => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+        }
+        Ok(())
+    }
+
+    /// Play `game` to its end, or until it is too long, following the tree in `nodes`
+    /// as long as possible. The nodes which are followed are added to `path`.
+    fn playout(
+        &mut self,
+        nodes: &mut Vec<Node>,
+        path: &mut Vec<usize>,
+        game: &mut Game,
+    ) -> Result<(), HandleError> {
+        let mut in_tree = true;
+        let mut steps = 0;
+        while game.substep != Substep::GameEnded && steps < self.config.max_playout_steps {
+            for msg in next_step(game) {
+                game.handle_message(&msg)?;
+                if let Message::Query(pid, query) = &msg {
+                    let answer = if in_tree {
+                        let node = path[path.len() - 1];
+                        let (child, expanded) = self.select(nodes, node, *pid, query);
+                        path.push(child);
+                        in_tree = !expanded;
+                        nodes[child].answer.clone().expect("child without answer")
+                    } else if self.config.heuristic_playouts {
+                        HeuristicAgent().answer_in_game(game, *pid, query)
+                    } else {
+                        random_answer(query, &mut self.rng)
+                    };
+                    game.maybe_answer = Some(answer);
+                }
+            }
+            steps += 1;
+        }
+        Ok(())
+    }
+
+    /// Choose the child of `node_id` to follow for `query`, expanding the tree if there
+    /// is an answer which has not been tried yet. Returns the child and whether it is new.
+    fn select(
+        &mut self,
+        nodes: &mut Vec<Node>,
+        node_id: usize,
+        player_id: PlayerID,
+        query: &Query,
+    ) -> (usize, bool) {
+        let candidates = candidate_answers(query, &mut self.rng);
+        let mut available = Vec::new();
+        let mut untried = Vec::new();
+        for answer in candidates {
+            let child = nodes[node_id].children.iter().find(|c| {
+                nodes[**c].player_id == player_id && nodes[**c].answer.as_ref() == Some(&answer)
+            });
+            match child {
+                Some(child) => available.push(*child),
+                None => untried.push(answer),
+            }
+        }
+        for child in &available {
+            nodes[*child].availability += 1;
+        }
+
+        if let Some(answer) = untried.choose(&mut self.rng) {
+            let child = nodes.len();
+            nodes.push(Node {
+                answer: Some(answer.clone()),
+                player_id,
+                visits: 0,
+                availability: 1,
+                reward: 0.0,
+                children: Vec::new(),
+            });
+            nodes[node_id].children.push(child);
+            return (child, true);
+        }
+
+        let ucb = |child: usize| {
+            let node = &nodes[child];
+            let visits = node.visits as f64;
+            node.reward / visits
+                + self.config.exploration * ((node.availability as f64).ln() / visits).sqrt()
+        };
+        let best = available
+            .iter()
+            .copied()
+            .max_by(|a, b| ucb(*a).total_cmp(&ucb(*b)))
+            .expect("query without answers");
+        (best, false)
+    }
+}
+
+impl PlayerAgent for MctsAgent {
+    fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+        let player_id = view.player_id;
+        let candidates = candidate_answers(query, &mut self.rng);
+        if candidates.len() == 1 {
+            return candidates[0].clone();
+        }
+
+        let root = Node {
+            answer: None,
+            player_id,
+            visits: 0,
+            availability: 0,
+            reward: 0.0,
+            children: Vec::new(),
+        };
+        let mut nodes = vec![root];
+        let start = Instant::now();
+        for _ in 0..self.config.iterations {
+            if start.elapsed() > self.config.time_budget {
+                break;
+            }
+            // a failed playout is skipped, the others still decide the answer
+            let _ = self.iterate(&mut nodes, view);
+        }
+
+        // the pending query of the game is the first query of every playout
+        nodes[0]
+            .children
+            .iter()
+            .map(|child| &nodes[*child])
+            .filter(|child| child.player_id == player_id)
+            .max_by_key(|child| child.visits)
+            .and_then(|child| child.answer.clone())
+            .unwrap_or_else(|| candidates[0].clone())
+    }
+}
+
+/// The answers to `query` considered by the search.
+/// Discarding can be done in too many ways, so only a sample of them is taken.
+fn candidate_answers(query: &Query, rng: &mut GameRng) -> Vec<Answer> {
+    match query {
+        Query::PriorityAction(actions) => actions
+            .iter()
+            .map(|action| Answer::PriorityAction(action.clone()))
+            .collect(),
+        Query::Discard(cards, n) => {
+            let n = *n as usize;
+            if nb_combinations(cards.len(), n) <= MAX_CANDIDATES {
+                return combinations(cards.len(), n)
+                    .into_iter()
+                    .map(|positions| Answer::Discard(positions.iter().map(|i| cards[*i]).collect()))
+                    .collect();
+            }
+            let mut answers: Vec<Answer> = Vec::new();
+            for _ in 0..MAX_CANDIDATES {
+                // the positions of the discarded cards are kept in order
+                // so that the same selection always gives the same answer
+                let mut positions = index::sample(rng, cards.len(), n).into_vec();
+                positions.sort_unstable();
+                let answer = Answer::Discard(positions.iter().map(|i| cards[*i]).collect());
+                if !answers.contains(&answer) {
+                    answers.push(answer);
+                }
+            }
+            answers
+        }
+        // taking back moves is not part of the game being searched, it is always allowed
+        Query::AllowUndo(_) => vec![Answer::AllowUndo(true)],
+        // nor are the decisions between the games of a match
+        Query::PlayFirst => vec![Answer::PlayFirst(true)],
+        Query::Sideboard(main, _) => vec![Answer::Sideboard(main.clone())],
+    }
+}
+
+/// Number of ways to choose `k` out of `n` items, saturating for large results
+fn nb_combinations(n: usize, k: usize) -> usize {
+    (0..k.min(n - k)).fold(1usize, |acc, i| acc.saturating_mul(n - i) / (i + 1))
+}
+
+/// All ways to choose `k` out of the positions `0..n`, each in increasing order
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    let mut result = Vec::new();
+    for last in k - 1..n {
+        for mut combination in combinations(last, k - 1) {
+            combination.push(last);
+            result.push(combination);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::RandomAgent;
+    use crate::card::{load_cards, test_cards, CardDefID, Deck};
+    use crate::scenario::{run, Scenario};
+    use crate::{duel, validate_answer, PriorityAction, Step, User};
+    use std::sync::Arc;
+
+    const FOREST: CardDefID = 101;
+    const ELVES: CardDefID = 102;
+
+    fn quick_agent(seed: u64) -> MctsAgent {
+        MctsAgent::new(MctsConfig::batch(10), seed)
+    }
+
+    /// Fails the test on the first answer which does not fit its query
+    struct Validated(MctsAgent);
+
+    impl PlayerAgent for Validated {
+        fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+            let answer = self.0.answer(view, query);
+            assert!(
+                validate_answer(query, &answer),
+                "{:?} is not an answer to {:?}",
+                answer,
+                query
+            );
+            answer
+        }
+    }
+
+    #[test]
+    fn the_answers_of_the_search_are_valid() {
+        let user = |name: &str| User {
+            name: name.to_string(),
+        };
+        let mut agents: Vec<Box<dyn PlayerAgent>> = vec![
+            Box::new(Validated(quick_agent(1))),
+            Box::new(RandomAgent::new(2)),
+        ];
+        let game = duel(
+            user("Leo"),
+            Deck(vec![(FOREST, 10)]),
+            user("Marc"),
+            Deck(vec![(FOREST, 10)]),
+            &mut agents,
+            &mut Vec::new(),
+            Arc::new(load_cards()),
+            3,
+        )
+        .unwrap();
+        assert_eq!(game.substep, Substep::GameEnded);
+    }
+
+    #[test]
+    fn the_search_plays_a_land_rather_than_passing() {
+        let mut game = Scenario::new(Arc::new(load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[FOREST; 2])
+            .library(0, &[FOREST; 5])
+            .library(1, &[FOREST; 5])
+            .active_player(0)
+            .step(Step::PrecombatMain)
+            .build()
+            .unwrap();
+        run(&mut game, vec![]).unwrap();
+        let view = PlayerView::from_game(&game, 0);
+        let query = view.maybe_query.clone().unwrap();
+        assert!(matches!(&query, Query::PriorityAction(actions)
+            if actions.contains(&PriorityAction::Pass)));
+        let answer = quick_agent(7).answer(&view, &query);
+        assert!(matches!(
+            answer,
+            Answer::PriorityAction(PriorityAction::PlayLand(_))
+        ));
+    }
+
+    #[test]
+    fn a_determinization_only_guesses_the_hidden_cards() {
+        let game = Scenario::new(Arc::new(test_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[FOREST, ELVES])
+            .graveyard(0, &[ELVES])
+            .library(0, &[FOREST, ELVES, FOREST, ELVES])
+            .hand(1, &[ELVES, FOREST])
+            .battlefield(1, &[FOREST])
+            .library(1, &[FOREST, ELVES])
+            .active_player(0)
+            .step(Step::PrecombatMain)
+            .build()
+            .unwrap();
+        let view = PlayerView::from_game(&game, 0);
+        let mut rng = GameRng::seed_from_u64(5);
+        let mut library_orders = Vec::new();
+        for _ in 0..20 {
+            let guess = view.determinize(&mut rng);
+            for card in view.cards.values() {
+                let def_id = guess.cards[&card.id].def_id;
+                match card.def_id {
+                    Some(known) => assert_eq!(def_id, known),
+                    // the opponent has only shown forests
+                    None if card.owner_id == 1 => assert_eq!(def_id, FOREST),
+                    None => (),
+                }
+            }
+            assert_eq!(
+                format!("{:?}", guess.players),
+                format!("{:?}", view.players)
+            );
+            let library: Vec<_> = guess.players[0]
+                .library
+                .iter()
+                .map(|cid| guess.cards[cid].def_id)
+                .collect();
+            // the own library holds the cards of the deck list which are not seen
+            let mut sorted = library.clone();
+            sorted.sort();
+            assert_eq!(sorted, vec![FOREST, FOREST, ELVES, ELVES]);
+            library_orders.push(library);
+        }
+        library_orders.sort();
+        library_orders.dedup();
+        assert!(library_orders.len() > 1);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::card::CardRepository;
+use crate::{CardID, Game, HandleError, Message, MessageConsumer, ObjectKind};
+use std::io::{stdin, stdout, Write};
+use std::sync::Arc;
+
+/// A recorded game which can be stepped through in both directions.
+/// Going backwards replays the messages from the start on a fresh game,
+/// which gives the same state because applying messages is deterministic.
+pub struct Replay {
+    messages: Vec<Message>,
+    card_repository: Arc<CardRepository>,
+    game: Game,
+    /// number of messages applied to `game`
+    position: usize,
+}
+
+impl Replay {
+    pub fn new(messages: Vec<Message>, card_repository: Arc<CardRepository>) -> Self {
+        Replay {
+            messages,
+            game: Game::new(card_repository.clone()),
+            card_repository,
+            position: 0,
+        }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Bring the game to the state after the first `position` messages
+    pub fn seek(&mut self, position: usize) -> Result<(), HandleError> {
+        let position = position.min(self.messages.len());
+        if position < self.position {
+            self.game = Game::new(self.card_repository.clone());
+            self.position = 0;
+        }
+        while self.position < position {
+            self.game.handle_message(&self.messages[self.position])?;
+            self.position += 1;
+        }
+        Ok(())
+    }
+
+    /// Positions right after the start of each turn, the first turn has number 1
+    pub fn turn_starts(&self) -> Vec<usize> {
+        self.messages
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| matches!(msg, Message::BeginTurn(_)))
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    /// Number of the turn the game is in, 0 before the first turn
+    pub fn turn(&self) -> usize {
+        self.turn_starts()
+            .iter()
+            .filter(|start| **start <= self.position)
+            .count()
+    }
+}
+
+const HELP: &str = "Commands:
+  n [k]    step k messages forward (default 1)
+  b [k]    step k messages backward (default 1)
+  t <turn> jump to the start of a turn
+  g <pos>  go to the state after <pos> messages
+  p        print the state of the game
+  q        quit";
+
+/// Step through a recorded game interactively on the terminal
+pub fn run_replay(messages: Vec<Message>, card_repository: Arc<CardRepository>) {
+    let mut replay = Replay::new(messages, card_repository);
+    println!(
+        "Replay of {} messages in {} turns, h for help",
+        replay.len(),
+        replay.turn_starts().len()
+    );
+    loop {
+        print!(
+            "[{}/{} turn {}] > ",
+            replay.position(),
+            replay.len(),
+            replay.turn()
+        );
+        stdout().flush().expect("can not write to the terminal");
+        let mut line = String::new();
+        if stdin()
+            .read_line(&mut line)
+            .expect("can not read from the terminal")
+            == 0
+        {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("n");
+        let argument = words.next().map(|word| word.parse::<usize>());
+        let count = match argument {
+            None => 1,
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                println!("the argument must be a number");
+                continue;
+            }
+        };
+        let start = replay.position();
+        let target = match command {
+            "n" => start + count,
+            "b" => start.saturating_sub(count),
+            "g" if argument.is_some() => count,
+            "t" if argument.is_some() => match replay.turn_starts().get(count.wrapping_sub(1)) {
+                Some(turn_start) => *turn_start,
+                None => {
+                    println!("there is no turn {}", count);
+                    continue;
+                }
+            },
+            "p" => {
+                print_state(replay.game());
+                continue;
+            }
+            "q" => break,
+            _ => {
+                println!("{}", HELP);
+                continue;
+            }
+        };
+        if let Err(e) = replay.seek(target) {
+            println!(
+                "message {} can not be applied: {}",
+                replay.position() + 1,
+                e
+            );
+        }
+        if replay.position() > start && replay.position() - start <= 20 {
+            for msg in &replay.messages[start..replay.position()] {
+                println!("{:?}", msg);
+            }
+        }
+    }
+}
+
+/// Print the complete state of the game, including all hidden information
+pub fn print_state(game: &Game) {
+    println!(
+        "{:?} step, {:?}, active player {}, priority {}",
+        game.step, game.substep, game.active_player_id, game.priority_player_id
+    );
+    let card_names = |cards: &[CardID]| -> String {
+        cards
+            .iter()
+            .map(|cid| format!("{} [{}]", game.definition(*cid).display.name, cid))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    for team in &game.teams {
+        println!("team {}: life {}", team.id, team.life);
+        for pid in &team.player_ids {
+            let player = &game.players[*pid];
+            let mut status = String::new();
+            if player.has_lost {
+                status.push_str(" (lost)");
+            }
+            if player.has_passed {
+                status.push_str(" (passed)");
+            }
+            println!(
+                "  player {} {}{}, {} land(s) played",
+                player.id, player.name, status, player.lands_played
+            );
+            println!("    library (top last): {}", card_names(&player.library));
+            println!("    hand: {}", card_names(&player.hand));
+            println!("    graveyard: {}", card_names(&player.graveyard));
+            if !player.removed.is_empty() {
+                println!("    removed from the game: {}", card_names(&player.removed));
+            }
+            for object in game.objects.values() {
+                if object.controller_id == player.id {
+                    match object.kind {
+                        ObjectKind::Card(cid) => println!(
+                            "    object {}: {} [{}]",
+                            object.id,
+                            game.definition(cid).display.name,
+                            cid
+                        ),
+                        ObjectKind::ActivatedAbility(cid, n) => {
+                            println!("    object {}: ability {} of [{}]", object.id, n, cid)
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let stack: Vec<_> = game.stack.iter().map(|spell| spell.id).collect();
+    println!("stack (top last): {:?}", stack);
+    if let Some((pid, query)) = &game.maybe_query {
+        println!("player {} is asked {:?}", pid, query);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{PlayerAgent, RandomAgent};
+    use crate::card::{load_cards, Deck};
+    use crate::{duel, User};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Collector(Rc<RefCell<Vec<Message>>>);
+
+    impl MessageConsumer for Collector {
+        fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+            self.0.borrow_mut().push(msg.clone());
+            Ok(())
+        }
+    }
+
+    /// The messages of a duel between random agents
+    fn recorded_duel(card_repository: Arc<CardRepository>) -> Vec<Message> {
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let mut consumers: Vec<Box<dyn MessageConsumer>> =
+            vec![Box::new(Collector(messages.clone()))];
+        let mut agents: Vec<Box<dyn PlayerAgent>> =
+            vec![Box::new(RandomAgent::new(1)), Box::new(RandomAgent::new(2))];
+        let user = |name: &str| User {
+            name: name.to_string(),
+        };
+        duel(
+            user("Leo"),
+            Deck(vec![(101, 12)]),
+            user("Marc"),
+            Deck(vec![(101, 12)]),
+            &mut agents,
+            &mut consumers,
+            card_repository,
+            5,
+        )
+        .unwrap();
+        drop(consumers);
+        Rc::try_unwrap(messages).unwrap().into_inner()
+    }
+
+    /// The game after the first `position` messages, applied one by one
+    fn game_after(
+        messages: &[Message],
+        position: usize,
+        card_repository: Arc<CardRepository>,
+    ) -> String {
+        let mut game = Game::new(card_repository);
+        for msg in &messages[..position] {
+            game.handle_message(msg).unwrap();
+        }
+        serde_json::to_string(&game).unwrap()
+    }
+
+    #[test]
+    fn seeking_gives_the_game_after_the_messages_before() {
+        let card_repository = Arc::new(load_cards());
+        let messages = recorded_duel(card_repository.clone());
+        let mut replay = Replay::new(messages.clone(), card_repository.clone());
+        let starts = replay.turn_starts();
+        assert!(starts.len() >= 3);
+        // forward, forward again, back and to the end
+        for position in [starts[1], starts[2], starts[0], messages.len()] {
+            replay.seek(position).unwrap();
+            assert_eq!(replay.position(), position);
+            assert_eq!(
+                serde_json::to_string(replay.game()).unwrap(),
+                game_after(&messages, position, card_repository.clone())
+            );
+        }
+        // beyond the end is the end
+        replay.seek(messages.len() + 10).unwrap();
+        assert_eq!(replay.position(), messages.len());
+    }
+
+    #[test]
+    fn turns_start_with_their_begin_turn_message() {
+        let card_repository = Arc::new(load_cards());
+        let messages = recorded_duel(card_repository.clone());
+        let mut replay = Replay::new(messages.clone(), card_repository);
+        let starts = replay.turn_starts();
+        let nb_turns = messages
+            .iter()
+            .filter(|msg| matches!(msg, Message::BeginTurn(_)))
+            .count();
+        assert_eq!(starts.len(), nb_turns);
+        assert_eq!(replay.turn(), 0);
+        for (i, start) in starts.iter().enumerate() {
+            assert!(matches!(messages[start - 1], Message::BeginTurn(_)));
+            replay.seek(*start).unwrap();
+            assert_eq!(replay.turn(), i + 1);
+            replay.seek(start - 1).unwrap();
+            assert_eq!(replay.turn(), i);
+        }
+    }
+}
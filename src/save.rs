@@ -0,0 +1,184 @@
+//! Saved games store the complete state of a game in progress, so that it can be resumed.
+//!
+//! A save file has two lines of JSON: a header identifying the format and its version,
+//! like game logs have, and the `Game` as serialized by serde. The card definitions are
+//! not part of the file, they are taken from the card repository the game is loaded with.
+use crate::card::CardRepository;
+use crate::gamelog::LogHeader;
+use crate::{Game, HandleError, Message, MessageConsumer};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const SAVE_FORMAT: &str = "bote-saved-game";
+pub const SAVE_VERSION: u32 = 1;
+
+/// Write `game` to the file at `path`, replacing any existing file.
+/// The file is written next to its final place first, so an interrupted save
+/// leaves the previous one intact.
+pub fn save_game<P: AsRef<Path>>(game: &Game, path: P) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    let header = LogHeader {
+        format: SAVE_FORMAT.to_string(),
+        version: SAVE_VERSION,
+    };
+    serde_json::to_writer(&mut writer, &header)?;
+    writeln!(writer)?;
+    serde_json::to_writer(&mut writer, game)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(temporary, path)
+}
+
+/// Read the game saved at `path` and check that it is consistent, see `Game::check_invariants`,
+/// and that every card has a definition in `card_repository`.
+pub fn load_game<P: AsRef<Path>>(
+    path: P,
+    card_repository: Arc<CardRepository>,
+) -> std::io::Result<Game> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: LogHeader = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(Error::new(ErrorKind::InvalidData, "empty save file")),
+    };
+    if header.format != SAVE_FORMAT || header.version != SAVE_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "unsupported save format {} version {}",
+                header.format, header.version
+            ),
+        ));
+    }
+    let mut game: Game = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "the save file has no game",
+            ))
+        }
+    };
+    game.card_repository = card_repository;
+    let invalid = |e: HandleError| Error::new(ErrorKind::InvalidData, e);
+    game.check_invariants().map_err(invalid)?;
+    for card in game.cards.values() {
+        if !game.card_repository.contains_key(&card.def_id) {
+            return Err(invalid(HandleError::CardDefIdError(card.def_id)));
+        }
+    }
+    Ok(game)
+}
+
+/// A message consumer which saves the game whenever a player has to answer a query,
+/// so that the game can be resumed from the last decision if it is interrupted
+pub struct AutoSave {
+    path: PathBuf,
+    game: Game,
+}
+
+impl AutoSave {
+    pub fn new<P: AsRef<Path>>(path: P, card_repository: Arc<CardRepository>) -> Self {
+        AutoSave {
+            path: path.as_ref().to_path_buf(),
+            game: Game::new(card_repository),
+        }
+    }
+
+    /// Save into `path` a game which is continued from `game`
+    pub fn resuming<P: AsRef<Path>>(path: P, game: &Game) -> Self {
+        AutoSave {
+            path: path.as_ref().to_path_buf(),
+            game: game.clone(),
+        }
+    }
+}
+
+impl MessageConsumer for AutoSave {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        self.game.handle_message(msg)?;
+        if let Message::Query(_, _) = msg {
+            save_game(&self.game, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::load_cards;
+    use crate::scenario::{run, Scenario};
+    use crate::Step;
+
+    /// Save a duel in which Leo is asked to play a land, change it with `edit`
+    /// and load it again
+    fn load_edited(name: &str, edit: impl Fn(&mut serde_json::Value)) -> std::io::Result<Game> {
+        let card_repository = Arc::new(load_cards());
+        let mut game = Scenario::new(card_repository.clone())
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[101])
+            .library(0, &[101; 3])
+            .library(1, &[101; 3])
+            .active_player(0)
+            .step(Step::PrecombatMain)
+            .build()
+            .unwrap();
+        run(&mut game, vec![]).unwrap();
+        let path = std::env::temp_dir().join(format!("bote-{}-{}.save", name, std::process::id()));
+        save_game(&game, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let (header, saved) = text.split_once('\n').unwrap();
+        let mut saved: serde_json::Value = serde_json::from_str(saved).unwrap();
+        edit(&mut saved);
+        fs::write(&path, format!("{}\n{}\n", header, saved)).unwrap();
+        let result = load_game(&path, card_repository);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn a_saved_game_is_loaded_again() {
+        let game = load_edited("unchanged", |_| {}).unwrap();
+        assert_eq!(game.library(1).len(), 3);
+        assert!(game.maybe_query.is_some());
+    }
+
+    #[test]
+    fn teams_listing_players_who_do_not_exist_are_rejected() {
+        let error = load_edited("unknown", |saved| {
+            saved["teams"][0]["player_ids"] = serde_json::json!([0, 7])
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn teams_not_matching_their_players_are_rejected() {
+        let error = load_edited("other", |saved| {
+            saved["teams"][1]["player_ids"] = serde_json::json!([0, 1])
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error = load_edited("swapped", |saved| {
+            saved["teams"][0]["id"] = serde_json::json!(1)
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn queries_about_cards_which_do_not_exist_are_rejected() {
+        let error = load_edited("query", |saved| {
+            saved["maybe_query"][1]["PriorityAction"][1]["PlayLand"] = serde_json::json!(999)
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,279 @@
+//! Games set up in a given state, to try out a rule without playing up to it.
+//!
+//! A `Scenario` builds a `Game` at any step and substep, with the hands, libraries,
+//! graveyards, battlefields and life totals of the players given card by card.
+//! `run` then advances the game with a script of answers, returning the messages
+//! so that both the messages and the resulting state can be looked at.
+//! A scenario can also be continued with agents by `resume_game`.
+use crate::card::{CardDefID, CardRepository};
+use crate::filter::private_answer;
+use crate::{
+    dispatch, next_step, Answer, CardID, Game, GameRng, HandleError, Message, MessageConsumer,
+    Object, ObjectKind, ObjectLocation, PlayerID, Step, Substep, Team, TeamID,
+};
+use rand::SeedableRng;
+use std::sync::Arc;
+
+pub struct Scenario {
+    game: Game,
+    /// the first invalid id given to the builder, reported by `build`
+    error: Option<HandleError>,
+}
+
+impl Scenario {
+    /// A game without players, at the beginning of the untap step
+    pub fn new(card_repository: Arc<CardRepository>) -> Self {
+        let mut game = Game::new(card_repository);
+        game.substep = Substep::BeginOfStep;
+        Scenario { game, error: None }
+    }
+
+    /// Add a player on a team of their own, with the starting life of a duel
+    pub fn player(self, name: &str) -> Self {
+        self.team(&[name])
+    }
+
+    /// Add a team of players, with the starting life of a team of that size
+    pub fn team(mut self, names: &[&str]) -> Self {
+        let team_id = self.game.teams.len();
+        self.apply(Message::CreateTeam {
+            id: team_id,
+            life: Team::starting_life(names.len()),
+        });
+        for name in names {
+            self.apply(Message::CreatePlayer {
+                id: self.game.players.len(),
+                name: name.to_string(),
+                team_id,
+            });
+        }
+        self
+    }
+
+    pub fn life(mut self, team_id: TeamID, life: i32) -> Self {
+        match self.game.teams.get_mut(team_id) {
+            Some(team) => team.life = life,
+            None => self.fail(HandleError::TeamIdError(team_id)),
+        }
+        self
+    }
+
+    /// Put cards into the library of a player, the last one on top
+    pub fn library(mut self, player_id: PlayerID, def_ids: &[CardDefID]) -> Self {
+        for def_id in def_ids {
+            self.add_card(player_id, *def_id);
+        }
+        self
+    }
+
+    pub fn hand(mut self, player_id: PlayerID, def_ids: &[CardDefID]) -> Self {
+        for def_id in def_ids {
+            if let Some(card_id) = self.add_card(player_id, *def_id) {
+                let player = &mut self.game.players[player_id];
+                player.library.pop();
+                player.hand.push(card_id);
+            }
+        }
+        self
+    }
+
+    pub fn graveyard(mut self, player_id: PlayerID, def_ids: &[CardDefID]) -> Self {
+        for def_id in def_ids {
+            if let Some(card_id) = self.add_card(player_id, *def_id) {
+                let player = &mut self.game.players[player_id];
+                player.library.pop();
+                player.graveyard.push(card_id);
+            }
+        }
+        self
+    }
+
+    /// Put cards onto the battlefield under the control of their owner
+    pub fn battlefield(mut self, player_id: PlayerID, def_ids: &[CardDefID]) -> Self {
+        for def_id in def_ids {
+            if let Some(card_id) = self.add_card(player_id, *def_id) {
+                self.game.players[player_id].library.pop();
+                let object_id = self.game.get_id();
+                let object = Object {
+                    id: object_id,
+                    controller_id: player_id,
+                    kind: ObjectKind::Card(card_id),
+                    location: ObjectLocation::Battlefield,
+                };
+                self.game.objects.insert(object_id, object);
+                if let Some(card) = self.game.cards.get_mut(&card_id) {
+                    card.object_id = Some(object_id);
+                }
+            }
+        }
+        self
+    }
+
+    /// Number of lands the player has played this turn
+    pub fn lands_played(mut self, player_id: PlayerID, lands_played: u32) -> Self {
+        match self.game.player_mut(player_id) {
+            Ok(player) => player.lands_played = lands_played,
+            Err(e) => self.fail(e),
+        }
+        self
+    }
+
+    pub fn step(mut self, step: Step) -> Self {
+        self.game.step = step;
+        self
+    }
+
+    pub fn substep(mut self, substep: Substep) -> Self {
+        self.game.substep = substep;
+        self
+    }
+
+    /// Make it the turn of a player, who also gets priority.
+    /// Like at the beginning of a turn, the team of the player has played no land yet.
+    pub fn active_player(mut self, player_id: PlayerID) -> Self {
+        self.apply(Message::BeginTurn(player_id));
+        self.apply(Message::PlayerHasPriority(player_id));
+        self
+    }
+
+    pub fn priority_player(mut self, player_id: PlayerID) -> Self {
+        self.apply(Message::PlayerHasPriority(player_id));
+        self
+    }
+
+    /// Seed of the random number generator, for the shuffles to come
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.game.seed = seed;
+        self.game.rng = GameRng::seed_from_u64(seed);
+        self
+    }
+
+    /// The game set up so far, or the first invalid id given to the builder.
+    /// The game is checked with `Game::check_invariants`.
+    pub fn build(self) -> Result<Game, HandleError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.game.check_invariants()?;
+        Ok(self.game)
+    }
+
+    fn apply(&mut self, msg: Message) {
+        if let Err(e) = self.game.handle_message(&msg) {
+            self.fail(e);
+        }
+    }
+
+    fn fail(&mut self, e: HandleError) {
+        self.error.get_or_insert(e);
+    }
+
+    /// Add a card on top of the library of the player
+    fn add_card(&mut self, owner_id: PlayerID, def_id: CardDefID) -> Option<CardID> {
+        let id = self.game.get_id();
+        self.apply(Message::AddCard {
+            id,
+            owner_id,
+            def_id,
+        });
+        self.game.cards.contains_key(&id).then_some(id)
+    }
+}
+
+/// Advance `game` substep by substep, answering the queries in turn with `answers`.
+/// The answers use public ids, like those of agents and recorded answers, see
+/// `Game::public_id`, and taking back decisions is not supported.
+/// Stops when the game has ended, or at a query once the answers are used up,
+/// and returns the messages applied to the game.
+pub fn run(
+    game: &mut Game,
+    answers: impl IntoIterator<Item = Answer>,
+) -> Result<Vec<Message>, HandleError> {
+    let mut answers = answers.into_iter();
+    let mut messages = Vec::new();
+    // a query left pending by an earlier run is answered first
+    if game.maybe_query.is_some() && game.maybe_answer.is_none() {
+        match answers.next() {
+            Some(answer) => game.maybe_answer = Some(private_answer(game, &answer)),
+            None => return Ok(messages),
+        }
+    }
+    while game.substep != Substep::GameEnded {
+        for msg in next_step(game) {
+            dispatch(game, &mut [], &mut [], &msg)?;
+            messages.push(msg);
+            if let Some(Message::Query(_, _)) = messages.last() {
+                match answers.next() {
+                    Some(answer) => game.maybe_answer = Some(private_answer(game, &answer)),
+                    None => return Ok(messages),
+                }
+            }
+        }
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::load_cards;
+    use crate::{LossReason, Query};
+
+    const FOREST: CardDefID = 101;
+
+    #[test]
+    fn a_player_discards_down_to_seven_cards_in_the_cleanup_step() {
+        let mut game = Scenario::new(Arc::new(load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[FOREST; 9])
+            .library(0, &[FOREST; 3])
+            .library(1, &[FOREST; 3])
+            .active_player(0)
+            .step(Step::Cleanup)
+            .build()
+            .unwrap();
+        let messages = run(&mut game, vec![]).unwrap();
+        assert!(matches!(
+            messages.last(),
+            Some(Message::Query(0, Query::Discard(cards, 2))) if cards.len() == 9
+        ));
+
+        let discarded = game.hand(0)[..2].to_vec();
+        let public_ids = discarded.iter().map(|cid| game.public_id(*cid)).collect();
+        run(&mut game, vec![Answer::Discard(public_ids)]).unwrap();
+        assert_eq!(game.hand(0).len(), 7);
+        assert_eq!(game.graveyard(0), &discarded[..]);
+        // the next turn has begun
+        assert_eq!(game.step(), Step::Untap);
+    }
+
+    #[test]
+    fn a_player_who_draws_from_an_empty_library_loses() {
+        let mut game = Scenario::new(Arc::new(load_cards()))
+            .player("Leo")
+            .player("Marc")
+            .hand(0, &[FOREST; 2])
+            .battlefield(0, &[FOREST])
+            .library(1, &[FOREST; 3])
+            .active_player(0)
+            .step(Step::Draw)
+            .build()
+            .unwrap();
+        let messages = run(&mut game, vec![]).unwrap();
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::DrawFromEmpty(0))));
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::PlayerLoses(0, LossReason::EmptyLibrary))));
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::TeamWins(1))));
+        assert_eq!(game.substep(), Substep::GameEnded);
+        assert!(game.has_lost(0) && !game.has_lost(1));
+        assert_eq!(game.winning_team(), Some(1));
+        assert!(game.hand(0).is_empty() && game.battlefield(0).is_empty());
+        assert_eq!(game.life(0), 20);
+    }
+}
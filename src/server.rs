@@ -0,0 +1,360 @@
+//! A game server letting players on other machines take part in a game over TCP.
+//!
+//! Both directions of a connection carry JSON objects, one per line, as serialized by serde:
+//! `ClientMessage`s from the client and `ServerMessage`s from the server.
+//!
+//! 1. The client opens the connection and sends `Hello` with the `User` it plays as.
+//! 2. The server answers `Welcome` with the id of the player, or `Refused` with a reason
+//!    and closes the connection if no player of the game has that name.
+//!    `Welcome` also tells how many messages of the game have been sent so far.
+//! 3. The server sends the messages of the game as that player may see them, see
+//!    `filter::redact`, each wrapped in `ServerMessage::Message`. Card ids are public ids.
+//!    The first messages are those sent so far, as announced by `Welcome`.
+//! 4. Whenever a `Query` for the player arrives the client sends one `Answer`. An invalid
+//!    answer is followed by `RejectAnswer` and the same query again. Lines which can not
+//!    be read as a `ClientMessage` are reported with `Error` and otherwise ignored.
+//!    `Answer::Undo` is only valid in games which let the players take back decisions.
+//!
+//! The game starts once all players are connected. A client whose connection is lost
+//! can connect again with the same name at any time; it is welcomed as before and then
+//! sent the player's whole stream from the start of the game, from which it has to rebuild
+//! its state. The queries among the resent messages have been answered already, except
+//! for the last message if it is a query. A new connection for a player replaces any
+//! earlier one. Connections are welcomed whenever the server waits for an answer.
+//!
+//! Clients are not authenticated: a `Hello` with the name of a player who is already
+//! connected takes over the seat of that player, and the earlier connection is closed.
+//! A client which does not read its messages is disconnected once a write to it has
+//! been blocked for `WRITE_TIMEOUT`.
+use crate::agent::PlayerAgent;
+use crate::card::{CardRepository, Deck};
+use crate::filter::redact;
+use crate::view::PlayerView;
+use crate::{
+    multiplayer_game, Answer, Game, HandleError, Message, MessageConsumer, PlayerID, Query, User,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// What a client sends to the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Hello(User),
+    Answer(Answer),
+}
+
+/// What the server sends to a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Welcome {
+        player_id: PlayerID,
+        /// number of messages of the game sent so far, which follow
+        resync: usize,
+    },
+    Refused(String),
+    Message(Message),
+    Error(String),
+}
+
+/// Time a new connection has to say hello
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a write to a client may be blocked before the client is disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time between two checks for new connections
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What happens on the connections, as reported by the threads reading from them
+enum Event {
+    Connected(PlayerID, usize, TcpStream),
+    Line(PlayerID, usize, String),
+    Closed(PlayerID, usize),
+}
+
+/// The current connection of a player
+struct Connection {
+    id: usize,
+    stream: TcpStream,
+}
+
+struct Seat {
+    connection: Option<Connection>,
+    /// all messages sent to the player, to resend them after a reconnection
+    history: Vec<Message>,
+    /// lines received on the current connection which have not been read yet
+    lines: VecDeque<String>,
+}
+
+/// The connections of all players, shared by the agents and the consumer of the server
+struct Seats {
+    seats: Vec<Seat>,
+    events: Receiver<Event>,
+}
+
+impl Seats {
+    /// Send `msg` to the player, a failed or timed out write closes the connection
+    fn send(&mut self, player_id: PlayerID, msg: &ServerMessage) {
+        if let Some(connection) = &mut self.seats[player_id].connection {
+            if write_line(&mut connection.stream, msg).is_err() {
+                let _ = connection.stream.shutdown(Shutdown::Both);
+                self.seats[player_id].connection = None;
+            }
+        }
+    }
+
+    /// Wait for something to happen on the connections and take it into account
+    fn wait_event(&mut self) {
+        let event = self
+            .events
+            .recv()
+            .expect("the server stopped accepting connections");
+        match event {
+            Event::Connected(player_id, id, stream) => {
+                let seat = &mut self.seats[player_id];
+                if let Some(old) = seat.connection.replace(Connection { id, stream }) {
+                    let _ = old.stream.shutdown(Shutdown::Both);
+                }
+                seat.lines.clear();
+                let history: Vec<_> = seat.history.clone();
+                let welcome = ServerMessage::Welcome {
+                    player_id,
+                    resync: history.len(),
+                };
+                self.send(player_id, &welcome);
+                for msg in history {
+                    self.send(player_id, &ServerMessage::Message(msg));
+                }
+            }
+            Event::Line(player_id, id, line) => {
+                let seat = &mut self.seats[player_id];
+                if seat.connection.as_ref().map(|c| c.id) == Some(id) {
+                    seat.lines.push_back(line);
+                }
+            }
+            Event::Closed(player_id, id) => {
+                let seat = &mut self.seats[player_id];
+                if seat.connection.as_ref().map(|c| c.id) == Some(id) {
+                    seat.connection = None;
+                }
+            }
+        }
+    }
+}
+
+fn write_line(stream: &mut TcpStream, msg: &ServerMessage) -> io::Result<()> {
+    let mut line = serde_json::to_string(msg)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+/// The agent of a player connected to the server, waiting for the answers of the client
+struct RemoteAgent {
+    player_id: PlayerID,
+    seats: Rc<RefCell<Seats>>,
+    allow_undo: bool,
+}
+
+impl PlayerAgent for RemoteAgent {
+    fn may_undo(&self) -> bool {
+        self.allow_undo
+    }
+
+    fn answer(&mut self, _view: &PlayerView, _query: &Query) -> Answer {
+        // the query has already been sent by the consumer of the server
+        let mut seats = self.seats.borrow_mut();
+        loop {
+            match seats.seats[self.player_id].lines.pop_front() {
+                Some(line) => match serde_json::from_str(&line) {
+                    Ok(ClientMessage::Answer(answer)) => return answer,
+                    Ok(ClientMessage::Hello(_)) => {
+                        let error = ServerMessage::Error("already connected".to_string());
+                        seats.send(self.player_id, &error);
+                    }
+                    Err(e) => seats.send(self.player_id, &ServerMessage::Error(e.to_string())),
+                },
+                None => seats.wait_event(),
+            }
+        }
+    }
+}
+
+/// Sends every player the messages of the game they may see
+struct ServerConsumer {
+    game: Game,
+    seats: Rc<RefCell<Seats>>,
+}
+
+impl MessageConsumer for ServerConsumer {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        self.game.handle_message(msg)?;
+        let mut seats = self.seats.borrow_mut();
+        for player_id in 0..seats.seats.len() {
+            for msg in redact(&self.game, player_id, msg) {
+                seats.seats[player_id].history.push(msg.clone());
+                seats.send(player_id, &ServerMessage::Message(msg));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Play a free-for-all game between clients connecting to `listener`,
+/// see the module documentation for the protocol.
+/// The game starts once every player of `players` is connected. With `allow_undo`
+/// the players may take back their decisions, like at the terminal.
+pub fn serve(
+    listener: TcpListener,
+    players: Vec<(User, Deck)>,
+    mut consumers: Vec<Box<dyn MessageConsumer>>,
+    card_repository: Arc<CardRepository>,
+    seed: u64,
+    allow_undo: bool,
+) -> io::Result<Game> {
+    let names: Vec<String> = players.iter().map(|(user, _)| user.name.clone()).collect();
+    if (1..names.len()).any(|i| names[..i].contains(&names[i])) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "the players of a network game need distinct names",
+        ));
+    }
+    listener.set_nonblocking(true)?;
+    let (sender, events) = channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let accept_stop = stop.clone();
+    thread::spawn(move || accept_connections(listener, names, sender, accept_stop));
+
+    let seats = Seats {
+        seats: players
+            .iter()
+            .map(|_| Seat {
+                connection: None,
+                history: Vec::new(),
+                lines: VecDeque::new(),
+            })
+            .collect(),
+        events,
+    };
+    let seats = Rc::new(RefCell::new(seats));
+    while seats
+        .borrow()
+        .seats
+        .iter()
+        .any(|seat| seat.connection.is_none())
+    {
+        seats.borrow_mut().wait_event();
+    }
+
+    let mut agents: Vec<Box<dyn PlayerAgent>> = (0..players.len())
+        .map(|player_id| {
+            Box::new(RemoteAgent {
+                player_id,
+                seats: seats.clone(),
+                allow_undo,
+            }) as Box<dyn PlayerAgent>
+        })
+        .collect();
+    consumers.push(Box::new(ServerConsumer {
+        game: Game::new(card_repository.clone()),
+        seats: seats.clone(),
+    }));
+    let game = multiplayer_game(players, &mut agents, &mut consumers, card_repository, seed)
+        .map_err(io::Error::other);
+
+    stop.store(true, Ordering::Relaxed);
+    for seat in &mut seats.borrow_mut().seats {
+        if let Some(connection) = seat.connection.take() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+    game
+}
+
+/// Accept connections until `stop` is set. Every connection gets a thread of its own
+/// for the handshake and then the lines of the client, so that a client which is slow
+/// to say hello does not keep the others from connecting.
+fn accept_connections(
+    listener: TcpListener,
+    names: Vec<String>,
+    events: Sender<Event>,
+    stop: Arc<AtomicBool>,
+) {
+    let names = Arc::new(names);
+    let mut next_id = 0;
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                next_id += 1;
+                let id = next_id;
+                let names = names.clone();
+                let events = events.clone();
+                thread::spawn(move || serve_connection(stream, id, &names, events));
+            }
+            // no connection is waiting, or it failed before it could be accepted
+            Err(_) => thread::sleep(ACCEPT_INTERVAL),
+        }
+    }
+}
+
+/// Run the handshake of a new connection, then pass on the lines of the client
+fn serve_connection(stream: TcpStream, id: usize, names: &[String], events: Sender<Event>) {
+    if let Ok(Some((player_id, reader))) = handshake(stream, names) {
+        if let Ok(stream) = reader.get_ref().try_clone() {
+            if events.send(Event::Connected(player_id, id, stream)).is_ok() {
+                read_lines(reader, player_id, id, events);
+            }
+        }
+    }
+}
+
+/// Read the `Hello` of a new connection and find the player it is for.
+/// Returns `None` if the connection was refused.
+fn handshake(
+    stream: TcpStream,
+    names: &[String],
+) -> io::Result<Option<(PlayerID, BufReader<TcpStream>)>> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let refusal = match serde_json::from_str(&line) {
+        Ok(ClientMessage::Hello(user)) => match names.iter().position(|n| *n == user.name) {
+            Some(player_id) => {
+                reader.get_ref().set_read_timeout(None)?;
+                reader.get_ref().set_write_timeout(Some(WRITE_TIMEOUT))?;
+                return Ok(Some((player_id, reader)));
+            }
+            None => format!("there is no player named {} in this game", user.name),
+        },
+        Ok(ClientMessage::Answer(_)) => "the first message must be Hello".to_string(),
+        Err(e) => e.to_string(),
+    };
+    write_line(&mut writer, &ServerMessage::Refused(refusal))?;
+    writer.shutdown(Shutdown::Both)?;
+    Ok(None)
+}
+
+/// Pass on the lines received from a client until the connection is closed
+fn read_lines(reader: BufReader<TcpStream>, player_id: PlayerID, id: usize, events: Sender<Event>) {
+    for line in reader.lines() {
+        match line {
+            Ok(line) => {
+                if events.send(Event::Line(player_id, id, line)).is_err() {
+                    return;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    let _ = events.send(Event::Closed(player_id, id));
+}
@@ -0,0 +1,356 @@
+//! Play many games between two decks to compare them.
+//!
+//! Usage: simulate [--games N] [--threads N] [--seed N]
+//!                 [--deck1 DECK] [--deck2 DECK] [--agent1 AGENT] [--agent2 AGENT]
+//!                 [--playouts N] [--check]
+//!
+//! A deck is given as a file holding a deck list, see `card::parse_deck_list`,
+//! of which only the main deck is played.
+//! Game `i` is played with seed `seed + i`, which also seeds the agents, so a run
+//! can be repeated exactly. Which player goes first is decided in each game.
+//! The `mcts` agent plays `--playouts` playouts per decision, 100 by default,
+//! and at most 100 ms.
+//! With `--check` the rules of `Game::check_invariants` are checked after every message,
+//! which debug builds always do. A game breaking one of them is reported with its seed
+//! and left out of the results.
+use bote::agent::{named_agent, AGENT_NAMES};
+use bote::card::{self, parse_deck_list, Deck};
+use bote::mcts::{MctsConfig, BATCH_ITERATIONS};
+use bote::{
+    duel, HandleError, InvariantChecker, LossReason, Message, MessageConsumer, PlayerID, User,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+
+/// Quantile of the normal distribution for 95% confidence intervals
+const Z_95: f64 = 1.96;
+
+/// What is recorded of a game
+#[derive(Debug, Default)]
+struct GameResult {
+    first_player: Option<PlayerID>,
+    turns: u32,
+    winner: Option<PlayerID>,
+    losses: Vec<LossReason>,
+}
+
+/// Records the result of a game from its messages
+struct Recorder(Rc<RefCell<GameResult>>);
+
+impl MessageConsumer for Recorder {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        let mut result = self.0.borrow_mut();
+        match msg {
+            Message::BeginTurn(pid) => {
+                result.first_player.get_or_insert(*pid);
+                result.turns += 1;
+            }
+            // in a duel every team has one player, with the same id
+            Message::TeamWins(tid) => result.winner = Some(*tid),
+            Message::PlayerLoses(_, reason) => result.losses.push(*reason),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the first error of a consumer, which the game would ignore
+struct FirstError<C>(C, Rc<RefCell<Option<HandleError>>>);
+
+impl<C: MessageConsumer> MessageConsumer for FirstError<C> {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        if let Err(e) = self.0.handle_message(msg) {
+            self.1.borrow_mut().get_or_insert(e);
+        }
+        Ok(())
+    }
+}
+
+struct Settings {
+    games: u64,
+    threads: u64,
+    seed: u64,
+    decks: [Deck; 2],
+    agents: [String; 2],
+    mcts: MctsConfig,
+    check: bool,
+}
+
+fn play_game(
+    settings: &Settings,
+    card_repository: Arc<card::CardRepository>,
+    seed: u64,
+) -> Result<GameResult, HandleError> {
+    let agent = |player_id: usize| {
+        let agent_seed = seed.wrapping_mul(2).wrapping_add(player_id as u64);
+        named_agent(&settings.agents[player_id], agent_seed, &settings.mcts).expect("unknown agent")
+    };
+    let mut agents = vec![agent(0), agent(1)];
+    let result = Rc::new(RefCell::new(GameResult::default()));
+    let mut consumers: Vec<Box<dyn MessageConsumer>> = vec![Box::new(Recorder(result.clone()))];
+    let violation = Rc::new(RefCell::new(None));
+    if settings.check {
+        let checker = InvariantChecker::new(card_repository.clone());
+        consumers.push(Box::new(FirstError(checker, violation.clone())));
+    }
+    duel(
+        User {
+            name: "first".to_string(),
+        },
+        settings.decks[0].clone(),
+        User {
+            name: "second".to_string(),
+        },
+        settings.decks[1].clone(),
+        &mut agents,
+        &mut consumers,
+        card_repository,
+        seed,
+    )?;
+    if let Some(e) = violation.take() {
+        return Err(e);
+    }
+    drop(consumers);
+    Ok(Rc::try_unwrap(result)
+        .expect("the game is over")
+        .into_inner())
+}
+
+/// The counts over all games of a simulation
+#[derive(Debug, Default)]
+struct Summary {
+    games: u64,
+    wins: [u64; 2],
+    draws: u64,
+    /// games won by the player who took the first turn
+    first_player_wins: u64,
+    turns: u64,
+    /// the losses of all players by reason
+    losses: BTreeMap<LossReason, u64>,
+}
+
+fn summarize(results: &[GameResult]) -> Summary {
+    let mut summary = Summary {
+        games: results.len() as u64,
+        ..Summary::default()
+    };
+    for result in results {
+        match result.winner {
+            Some(player_id) => {
+                summary.wins[player_id] += 1;
+                if result.first_player == Some(player_id) {
+                    summary.first_player_wins += 1;
+                }
+            }
+            None => summary.draws += 1,
+        }
+        summary.turns += result.turns as u64;
+        for reason in &result.losses {
+            *summary.losses.entry(*reason).or_insert(0) += 1;
+        }
+    }
+    summary
+}
+
+/// The 95% Wilson score interval of the probability of an event seen `k` times in `n` trials
+fn wilson_interval(k: u64, n: u64) -> (f64, f64) {
+    if n == 0 {
+        return (0.0, 1.0);
+    }
+    let n = n as f64;
+    let p = k as f64 / n;
+    let z2 = Z_95 * Z_95;
+    let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half_width = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+    (
+        (center - half_width).max(0.0),
+        (center + half_width).min(1.0),
+    )
+}
+
+/// `k` out of `n` as a percentage with its confidence interval
+fn rate(k: u64, n: u64) -> String {
+    let (low, high) = wilson_interval(k, n);
+    let p = if n == 0 { 0.0 } else { k as f64 / n as f64 };
+    format!(
+        "{:5.1}% [{:.1}%, {:.1}%]",
+        100.0 * p,
+        100.0 * low,
+        100.0 * high
+    )
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let value = |flag: &str| {
+        args.iter().position(|arg| arg == flag).map(|i| {
+            args.get(i + 1)
+                .unwrap_or_else(|| panic!("{} must be followed by a value", flag))
+        })
+    };
+    let number = |flag: &str, default: u64| match value(flag) {
+        Some(arg) => arg
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be followed by a number", flag)),
+        None => default,
+    };
+    let card_repository = Arc::new(card::load_cards());
+    let deck = |flag: &str| match value(flag) {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).expect("can not read a deck list");
+            match parse_deck_list(&text, &card_repository) {
+                Ok(list) => list.main,
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        None => Deck(vec![(101, 10)]),
+    };
+    let agent = |flag: &str| match value(flag) {
+        Some(name) if AGENT_NAMES.contains(&name.as_str()) => name.clone(),
+        Some(name) => panic!("unknown agent {}, expected one of {:?}", name, AGENT_NAMES),
+        None => "heuristic".to_string(),
+    };
+    let available = thread::available_parallelism().map_or(1, |n| n.get() as u64);
+    let settings = Arc::new(Settings {
+        games: number("--games", 1000),
+        threads: number("--threads", available).max(1),
+        seed: number("--seed", rand::random()),
+        decks: [deck("--deck1"), deck("--deck2")],
+        agents: [agent("--agent1"), agent("--agent2")],
+        mcts: MctsConfig::batch(number("--playouts", BATCH_ITERATIONS as u64) as usize),
+        check: args.iter().any(|arg| arg == "--check"),
+    });
+    let workers: Vec<_> = (0..settings.threads)
+        .map(|worker| {
+            let settings = settings.clone();
+            let card_repository = card_repository.clone();
+            thread::spawn(move || {
+                (worker..settings.games)
+                    .step_by(settings.threads as usize)
+                    .map(|i| {
+                        let seed = settings.seed.wrapping_add(i);
+                        (seed, play_game(&settings, card_repository.clone(), seed))
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let mut results = Vec::new();
+    for worker in workers {
+        for (seed, result) in worker.join().expect("a simulation thread panicked") {
+            match result {
+                Ok(result) => results.push(result),
+                Err(e) => eprintln!("the game with seed {} failed: {}", seed, e),
+            }
+        }
+    }
+
+    let summary = summarize(&results);
+    let n = summary.games;
+    let nb_losses: u64 = summary.losses.values().sum();
+
+    println!(
+        "{} games, seeds {} to {}",
+        n,
+        settings.seed,
+        settings.seed.wrapping_add(settings.games.saturating_sub(1))
+    );
+    for player_id in 0..2 {
+        println!(
+            "deck {} ({}): {:5} wins {}",
+            player_id + 1,
+            settings.agents[player_id],
+            summary.wins[player_id],
+            rate(summary.wins[player_id], n)
+        );
+    }
+    println!("draws: {:5} {}", summary.draws, rate(summary.draws, n));
+    println!(
+        "first player wins: {}",
+        rate(summary.first_player_wins, n - summary.draws)
+    );
+    if n > 0 {
+        println!(
+            "average length: {:.1} turns",
+            summary.turns as f64 / n as f64
+        );
+    }
+    println!("losses:");
+    for (reason, count) in &summary.losses {
+        println!("  {:?}: {} {}", reason, count, rate(*count, nb_losses));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-4 && (actual.1 - expected.1).abs() < 1e-4,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn wilson_intervals() {
+        assert_close(wilson_interval(5, 10), (0.2366, 0.7634));
+        assert_close(wilson_interval(0, 10), (0.0, 0.2775));
+        assert_close(wilson_interval(10, 10), (0.7225, 1.0));
+        assert_close(wilson_interval(50, 100), (0.4038, 0.5962));
+        // nothing is known without trials
+        assert_eq!(wilson_interval(0, 0), (0.0, 1.0));
+        assert_eq!(rate(0, 0), "  0.0% [0.0%, 100.0%]");
+    }
+
+    #[test]
+    fn results_are_summed_up() {
+        let result = |first_player, turns, winner, losses: &[LossReason]| GameResult {
+            first_player: Some(first_player),
+            turns,
+            winner,
+            losses: losses.to_vec(),
+        };
+        let results = [
+            result(0, 10, Some(0), &[LossReason::EmptyLibrary]),
+            result(1, 7, Some(0), &[LossReason::Life]),
+            result(1, 12, Some(1), &[LossReason::EmptyLibrary]),
+            result(0, 30, None, &[LossReason::Life, LossReason::Life]),
+        ];
+        let summary = summarize(&results);
+        assert_eq!(summary.games, 4);
+        assert_eq!(summary.wins, [2, 1]);
+        assert_eq!(summary.draws, 1);
+        assert_eq!(summary.first_player_wins, 2);
+        assert_eq!(summary.turns, 59);
+        let losses: Vec<_> = summary.losses.into_iter().collect();
+        assert_eq!(
+            losses,
+            vec![(LossReason::Life, 3), (LossReason::EmptyLibrary, 2)]
+        );
+
+        let summary = summarize(&[]);
+        assert_eq!((summary.games, summary.draws, summary.turns), (0, 0, 0));
+    }
+
+    #[test]
+    fn the_first_broken_invariant_is_kept() {
+        let violation = Rc::new(RefCell::new(None));
+        let checker = InvariantChecker::new(Arc::new(card::load_cards()));
+        let mut consumer = FirstError(checker, violation.clone());
+        consumer.handle_message(&Message::BeginTurn(0)).unwrap();
+        consumer.handle_message(&Message::BeginTurn(1)).unwrap();
+        assert!(matches!(
+            violation.take(),
+            Some(HandleError::InvalidMessage(msg, _)) if matches!(*msg, Message::BeginTurn(0))
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::agent::PlayerAgent;
+use crate::card::{parse_deck_list, CardDefID};
+use crate::view::PlayerView;
+use crate::{validate_answer, Answer, CardID, ObjectKind, PriorityAction, Query};
+use std::io::{stdin, stdout, Write};
+
+/// An agent which lets a human play through the terminal.
+/// The game is shown from the point of view of the player and the options
+/// of each query are listed with numbers to choose from.
+/// Instead of a number `u` asks to take back the last decision.
+/// Between the games of a match the new main deck is typed in as a deck list.
+pub struct TerminalAgent();
+
+impl PlayerAgent for TerminalAgent {
+    fn may_undo(&self) -> bool {
+        true
+    }
+
+    fn answer(&mut self, view: &PlayerView, query: &Query) -> Answer {
+        println!();
+        print_view(view);
+        match query {
+            Query::PriorityAction(actions) => {
+                println!("Choose an action:");
+                for (i, action) in actions.iter().enumerate() {
+                    match action {
+                        PriorityAction::Pass => println!("  {}) pass", i + 1),
+                        PriorityAction::PlayLand(cid) => {
+                            println!("  {}) play land {}", i + 1, view.card_name(*cid))
+                        }
+                    }
+                }
+            }
+            Query::Discard(cards, n) => {
+                println!("Choose {} card(s) to discard:", n);
+                for (i, cid) in cards.iter().enumerate() {
+                    println!("  {}) {}", i + 1, view.card_name(*cid));
+                }
+            }
+            Query::AllowUndo(pid) => {
+                println!(
+                    "{} wants to take back their last decision. Allow it?",
+                    view.players[*pid].name
+                );
+                println!("  1) yes\n  2) no");
+            }
+            Query::PlayFirst => {
+                println!("Do you take the first turn of the next game?");
+                println!("  1) play first\n  2) draw first");
+            }
+            Query::Sideboard(main, sideboard) => {
+                print_cards(view, "Main deck", main);
+                print_cards(view, "Sideboard", sideboard);
+                println!("Enter the new main deck, one line like `4 Forest` per card and");
+                println!("an empty line to finish, or only an empty line to keep it:");
+            }
+        }
+        read_answer(view, query, read_line)
+    }
+}
+
+/// Read lines with `read_line` until they make a valid answer to `query`
+fn read_answer(view: &PlayerView, query: &Query, mut read_line: impl FnMut() -> String) -> Answer {
+    loop {
+        let line = read_line();
+        let in_game = matches!(query, Query::PriorityAction(_) | Query::Discard(_, _));
+        if line.trim() == "u" && in_game {
+            return Answer::Undo;
+        }
+        let choices = line
+            .split_whitespace()
+            .map(|word| word.parse::<usize>().ok()?.checked_sub(1))
+            .collect::<Option<Vec<usize>>>();
+        let answer = match query {
+            Query::PriorityAction(actions) => choices
+                .filter(|choices| choices.len() == 1)
+                .and_then(|choices| actions.get(choices[0]))
+                .map(|action| Answer::PriorityAction(action.clone())),
+            Query::Discard(cards, _) => choices
+                .and_then(|choices| {
+                    choices
+                        .iter()
+                        .map(|i| cards.get(*i).cloned())
+                        .collect::<Option<Vec<CardID>>>()
+                })
+                .map(Answer::Discard),
+            Query::AllowUndo(_) => match choices.as_deref() {
+                Some([0]) => Some(Answer::AllowUndo(true)),
+                Some([1]) => Some(Answer::AllowUndo(false)),
+                _ => None,
+            },
+            Query::PlayFirst => match choices.as_deref() {
+                Some([0]) => Some(Answer::PlayFirst(true)),
+                Some([1]) => Some(Answer::PlayFirst(false)),
+                _ => None,
+            },
+            Query::Sideboard(main, _) if line.trim().is_empty() => {
+                Some(Answer::Sideboard(main.clone()))
+            }
+            Query::Sideboard(_, _) => {
+                let mut text = line.clone();
+                loop {
+                    let line = read_line();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    text.push_str(&line);
+                }
+                match parse_deck_list(&text, view.card_repository()) {
+                    Ok(list) => Some(Answer::Sideboard(list.main.0)),
+                    Err(e) => {
+                        println!("{}", e);
+                        None
+                    }
+                }
+            }
+        };
+        match answer {
+            Some(answer) if validate_answer(query, &answer) => return answer,
+            _ => println!("Invalid choice, please try again."),
+        }
+    }
+}
+
+/// Read a line of input from the terminal
+fn read_line() -> String {
+    print!("> ");
+    stdout().flush().expect("can not write to the terminal");
+    let mut line = String::new();
+    let nb_read = stdin()
+        .read_line(&mut line)
+        .expect("can not read from the terminal");
+    if nb_read == 0 {
+        panic!("the terminal was closed while waiting for an answer");
+    }
+    line
+}
+
+fn print_cards(view: &PlayerView, title: &str, cards: &[(CardDefID, usize)]) {
+    println!("{}:", title);
+    for (def_id, count) in cards {
+        println!("    {} {}", count, view.definition_name(*def_id));
+    }
+}
+
+/// Print the game as far as it is visible to the player
+fn print_view(view: &PlayerView) {
+    println!("--- {:?} step, {:?} ---", view.step, view.substep);
+    for player in &view.players {
+        let mut status = format!(
+            "{}: life {}, library {}, hand {}, graveyard {}",
+            player.name,
+            view.teams[player.team_id].life,
+            player.library.len(),
+            player.hand.len(),
+            player.graveyard.len()
+        );
+        if player.id == view.active_player_id {
+            status.push_str(" (active)");
+        }
+        if player.has_lost {
+            status.push_str(" (lost)");
+        }
+        println!("{}", status);
+        let mut permanents: Vec<_> = view
+            .objects
+            .values()
+            .filter(|object| object.controller_id == player.id)
+            .collect();
+        permanents.sort_by_key(|object| object.id);
+        for object in permanents {
+            match object.kind {
+                ObjectKind::Card(cid) => println!("    {}", view.card_name(cid)),
+                ObjectKind::ActivatedAbility(cid, n) => {
+                    println!("    ability {} of {}", n, view.card_name(cid))
+                }
+            }
+        }
+    }
+    if view.stack.is_empty() {
+        println!("The stack is empty.");
+    } else {
+        println!("Stack (top first):");
+        for spell in view.stack.iter().rev() {
+            println!("    spell {}", spell.id);
+        }
+    }
+    println!("Your hand:");
+    for cid in &view.players[view.player_id].hand {
+        println!("    {}", view.card_name(*cid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::load_cards;
+    use std::sync::Arc;
+
+    #[test]
+    fn an_invalid_choice_is_asked_again() {
+        let view = PlayerView::new(0, Arc::new(load_cards()));
+        let query = Query::Discard(vec![1001, 1002, 1003], 2);
+        // too few cards, twice the same card, a card which is not offered
+        let mut lines = vec!["1\n", "1 1\n", "4 2\n", "3 1\n", "2 3\n"].into_iter();
+        let answer = read_answer(&view, &query, || lines.next().unwrap().to_string());
+        assert_eq!(answer, Answer::Discard(vec![1003, 1001]));
+        assert_eq!(lines.len(), 1);
+
+        let query = Query::PriorityAction(vec![PriorityAction::Pass]);
+        let mut lines = vec!["pass\n", "2\n", "u\n"].into_iter();
+        let answer = read_answer(&view, &query, || lines.next().unwrap().to_string());
+        assert_eq!(answer, Answer::Undo);
+    }
+}
@@ -0,0 +1,412 @@
+//! Play a tournament of best-of-three matches between many decks.
+//!
+//! Usage: tournament <directory> [--swiss] [--rounds N] [--agent AGENT] [--playouts N]
+//!                   [--seed N]
+//!
+//! Every file of the directory holds a deck list, as read by `card::parse_deck_list`,
+//! and the deck is named after the file. Without `--swiss` every deck meets every other
+//! deck once. With `--swiss` the decks are paired for a number of rounds, by default
+//! enough to find a single undefeated deck, each time with a deck of about the same
+//! standing which they have not met yet, or again if they have met all of them. All players are played by the same agent and
+//! there is no sideboarding, only the main decks are played. The `mcts` agent plays
+//! `--playouts` playouts per decision, 100 by default, and at most 100 ms.
+//! The standings, the game wins between every pair of decks and the Elo rating of
+//! every deck, updated after every game, are printed at the end.
+use bote::agent::{named_agent, AGENT_NAMES};
+use bote::card::{self, parse_deck_list, Deck};
+use bote::matches::Match;
+use bote::mcts::{MctsConfig, BATCH_ITERATIONS};
+use bote::{MessageConsumer, User};
+use std::fs;
+use std::sync::Arc;
+
+/// Match points for a match won, or a round without opponent
+const WIN_POINTS: u32 = 3;
+/// Match points for a drawn match
+const DRAW_POINTS: u32 = 1;
+/// Rating of every deck at the start of the tournament
+const ELO_START: f64 = 1500.0;
+/// Largest change of a rating after one game
+const ELO_K: f64 = 32.0;
+
+struct Entry {
+    name: String,
+    deck: Deck,
+    points: u32,
+    /// matches won, lost and drawn
+    record: (u32, u32, u32),
+    game_wins: u32,
+    games: u32,
+    elo: f64,
+    had_bye: bool,
+}
+
+struct Tournament {
+    entries: Vec<Entry>,
+    /// `game_wins[i][j]` is the number of games deck `i` won against deck `j`
+    game_wins: Vec<Vec<u32>>,
+    /// whether deck `i` has met deck `j`
+    met: Vec<Vec<bool>>,
+    agent: String,
+    mcts: MctsConfig,
+    seed: u64,
+    matches_played: u64,
+    card_repository: Arc<card::CardRepository>,
+}
+
+impl Tournament {
+    /// Count a game between two decks and update their ratings
+    fn record_game(&mut self, a: usize, b: usize, winner: Option<usize>) {
+        self.entries[a].games += 1;
+        self.entries[b].games += 1;
+        let score_a = match winner {
+            Some(w) => {
+                self.entries[w].game_wins += 1;
+                self.game_wins[w][if w == a { b } else { a }] += 1;
+                if w == a {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            None => 0.5,
+        };
+        let (elo_a, elo_b) = elo_update(self.entries[a].elo, self.entries[b].elo, score_a);
+        self.entries[a].elo = elo_a;
+        self.entries[b].elo = elo_b;
+    }
+
+    /// Play a best-of-three match between two decks
+    fn play_match(&mut self, a: usize, b: usize) {
+        let seed = self.seed.wrapping_add(self.matches_played);
+        self.matches_played += 1;
+        let agent = |i: u64| {
+            let seed = seed.wrapping_mul(2).wrapping_add(i);
+            named_agent(&self.agent, seed, &self.mcts).expect("unknown agent")
+        };
+        let mut agents = vec![agent(0), agent(1)];
+        let mut consumers: Vec<Box<dyn MessageConsumer>> = Vec::new();
+        let player = |i: usize| {
+            let user = User {
+                name: self.entries[i].name.clone(),
+            };
+            (user, self.entries[i].deck.clone(), Deck(Vec::new()))
+        };
+        let mut the_match = Match::best_of_three(player(a), player(b)).without_sideboarding();
+        let result = the_match.play(
+            &mut agents,
+            &mut consumers,
+            self.card_repository.clone(),
+            seed,
+        );
+        if let Err(e) = result {
+            eprintln!("the match with seed {} was stopped: {}", seed, e);
+        }
+        // the decks have the seats 0 and 1 of the match
+        let record = the_match.record();
+        for winner in &record.games {
+            self.record_game(a, b, winner.map(|seat| [a, b][seat]));
+        }
+        self.met[a][b] = true;
+        self.met[b][a] = true;
+        match record.winner().map(|seat| [a, b][seat]) {
+            Some(winner) => {
+                let loser = if winner == a { b } else { a };
+                self.entries[winner].points += WIN_POINTS;
+                self.entries[winner].record.0 += 1;
+                self.entries[loser].record.1 += 1;
+            }
+            None => {
+                for i in [a, b] {
+                    self.entries[i].points += DRAW_POINTS;
+                    self.entries[i].record.2 += 1;
+                }
+            }
+        }
+    }
+
+    fn round_robin(&mut self) {
+        for (a, b) in round_robin_pairs(self.entries.len()) {
+            self.play_match(a, b);
+        }
+    }
+
+    fn swiss(&mut self, rounds: u32) {
+        for _ in 0..rounds {
+            let had_bye: Vec<bool> = self.entries.iter().map(|e| e.had_bye).collect();
+            let (pairs, bye) = swiss_pairs(&self.standings(), &self.met, &had_bye);
+            if let Some(i) = bye {
+                let entry = &mut self.entries[i];
+                entry.had_bye = true;
+                entry.points += WIN_POINTS;
+                entry.record.0 += 1;
+            }
+            for (a, b) in pairs {
+                self.play_match(a, b);
+            }
+        }
+    }
+
+    /// The decks from first to last, by match points, then game wins and then rating
+    fn standings(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.entries.len()).collect();
+        let game_win_rate = |e: &Entry| e.game_wins as f64 / e.games.max(1) as f64;
+        order.sort_by(|a, b| {
+            let (a, b) = (&self.entries[*a], &self.entries[*b]);
+            b.points
+                .cmp(&a.points)
+                .then(game_win_rate(b).total_cmp(&game_win_rate(a)))
+                .then(b.elo.total_cmp(&a.elo))
+        });
+        order
+    }
+
+    fn print(&self) {
+        let width = self.entries.iter().map(|e| e.name.len()).max().unwrap_or(0);
+        println!("Standings");
+        for (rank, i) in self.standings().into_iter().enumerate() {
+            let e = &self.entries[i];
+            println!(
+                "{:3}. {:width$}  {:3} points  matches {}-{}-{}  games {}/{}  Elo {:.0}",
+                rank + 1,
+                e.name,
+                e.points,
+                e.record.0,
+                e.record.1,
+                e.record.2,
+                e.game_wins,
+                e.games,
+                e.elo,
+                width = width
+            );
+        }
+        println!();
+        println!("Game wins of each deck against the others");
+        print!("{:width$}", "", width = width);
+        for e in &self.entries {
+            print!("  {:>width$}", e.name, width = width);
+        }
+        println!();
+        for (a, e) in self.entries.iter().enumerate() {
+            print!("{:width$}", e.name, width = width);
+            for b in 0..self.entries.len() {
+                let cell = if self.met[a][b] {
+                    format!("{}-{}", self.game_wins[a][b], self.game_wins[b][a])
+                } else {
+                    String::new()
+                };
+                print!("  {:>width$}", cell, width = width);
+            }
+            println!();
+        }
+    }
+}
+
+/// The matches of a round robin between `n` decks, every deck meets every other deck once
+fn round_robin_pairs(n: usize) -> Vec<(usize, usize)> {
+    (0..n)
+        .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+        .collect()
+}
+
+/// The matches of a round of a Swiss tournament and the deck which sits it out, if any.
+/// `standings` lists the decks from first to last, `met[a][b]` tells whether decks `a`
+/// and `b` have met and `had_bye[a]` whether deck `a` has sat out a round.
+/// With an odd number of decks the lowest deck without a bye sits out the round,
+/// or the lowest deck if all had one. From the top of the standings every deck meets
+/// the next deck it has not met yet. A deck which has met all the decks left in the
+/// round meets the next of them again.
+fn swiss_pairs(
+    standings: &[usize],
+    met: &[Vec<bool>],
+    had_bye: &[bool],
+) -> (Vec<(usize, usize)>, Option<usize>) {
+    let mut unpaired = standings.to_vec();
+    let mut bye = None;
+    if unpaired.len() % 2 == 1 {
+        let i = unpaired
+            .iter()
+            .rposition(|i| !had_bye[*i])
+            .unwrap_or(unpaired.len() - 1);
+        bye = Some(unpaired.remove(i));
+    }
+    let mut pairs = Vec::new();
+    while !unpaired.is_empty() {
+        let a = unpaired.remove(0);
+        // a deck which has met all the decks left meets the first of them again
+        let j = unpaired.iter().position(|b| !met[a][*b]).unwrap_or(0);
+        pairs.push((a, unpaired.remove(j)));
+    }
+    (pairs, bye)
+}
+
+/// The ratings of two decks after a game in which the first scored `score_a`,
+/// 1 for a win, 0.5 for a draw and 0 for a loss
+fn elo_update(elo_a: f64, elo_b: f64, score_a: f64) -> (f64, f64) {
+    let expected_a = 1.0 / (1.0 + 10f64.powf((elo_b - elo_a) / 400.0));
+    let change = ELO_K * (score_a - expected_a);
+    (elo_a + change, elo_b - change)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 || args[1].starts_with("--") {
+        eprintln!(
+            "usage: tournament <directory> [--swiss] [--rounds N] [--agent AGENT] \
+             [--playouts N] [--seed N]"
+        );
+        std::process::exit(2);
+    }
+    let value = |flag: &str| {
+        args.iter().position(|arg| arg == flag).map(|i| {
+            args.get(i + 1)
+                .unwrap_or_else(|| panic!("{} must be followed by a value", flag))
+        })
+    };
+    let seed: u64 = match value("--seed") {
+        Some(arg) => arg.parse().expect("--seed must be followed by a number"),
+        None => rand::random(),
+    };
+    let agent = match value("--agent") {
+        Some(name) if AGENT_NAMES.contains(&name.as_str()) => name.clone(),
+        Some(name) => panic!("unknown agent {}, expected one of {:?}", name, AGENT_NAMES),
+        None => "heuristic".to_string(),
+    };
+    let playouts = match value("--playouts") {
+        Some(arg) => arg
+            .parse()
+            .expect("--playouts must be followed by a number"),
+        None => BATCH_ITERATIONS,
+    };
+
+    let card_repository = Arc::new(card::load_cards());
+    let mut paths: Vec<_> = fs::read_dir(&args[1])
+        .expect("can not read the deck directory")
+        .map(|entry| entry.expect("can not read the deck directory").path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    let mut entries = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path).expect("can not read a deck list");
+        let deck = match parse_deck_list(&text, &card_repository) {
+            Ok(list) => list.main,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        };
+        let name = path
+            .file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        entries.push(Entry {
+            name,
+            deck,
+            points: 0,
+            record: (0, 0, 0),
+            game_wins: 0,
+            games: 0,
+            elo: ELO_START,
+            had_bye: false,
+        });
+    }
+    if entries.len() < 2 {
+        eprintln!("a tournament needs at least two decks");
+        std::process::exit(2);
+    }
+
+    let n = entries.len();
+    let mut tournament = Tournament {
+        entries,
+        game_wins: vec![vec![0; n]; n],
+        met: vec![vec![false; n]; n],
+        agent,
+        mcts: MctsConfig::batch(playouts),
+        seed,
+        matches_played: 0,
+        card_repository,
+    };
+    println!("Tournament of {} decks with seed {}", n, seed);
+    if args.iter().any(|arg| arg == "--swiss") {
+        // enough rounds for a single deck to win all its matches
+        let rounds = match value("--rounds") {
+            Some(arg) => arg.parse().expect("--rounds must be followed by a number"),
+            None => usize::BITS - (n - 1).leading_zeros(),
+        };
+        tournament.swiss(rounds);
+    } else {
+        tournament.round_robin();
+    }
+    tournament.print();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `met` for `n` decks of which the given pairs have met
+    fn met(n: usize, pairs: &[(usize, usize)]) -> Vec<Vec<bool>> {
+        let mut met = vec![vec![false; n]; n];
+        for (a, b) in pairs {
+            met[*a][*b] = true;
+            met[*b][*a] = true;
+        }
+        met
+    }
+
+    #[test]
+    fn every_deck_meets_every_other_deck_once_in_a_round_robin() {
+        let pairs = round_robin_pairs(4);
+        assert_eq!(pairs, vec![(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)]);
+        assert!(round_robin_pairs(1).is_empty());
+    }
+
+    #[test]
+    fn swiss_pairs_follow_the_standings() {
+        let (pairs, bye) = swiss_pairs(&[2, 0, 3, 1], &met(4, &[]), &[false; 4]);
+        assert_eq!(pairs, vec![(2, 0), (3, 1)]);
+        assert_eq!(bye, None);
+    }
+
+    #[test]
+    fn decks_which_have_met_are_not_paired_again() {
+        let (pairs, _) = swiss_pairs(&[0, 1, 2, 3], &met(4, &[(0, 1)]), &[false; 4]);
+        assert_eq!(pairs, vec![(0, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn a_deck_which_has_met_all_others_left_meets_one_again() {
+        let played = met(4, &[(0, 1), (2, 3), (0, 2), (1, 3)]);
+        let (pairs, _) = swiss_pairs(&[0, 1, 2, 3], &played, &[false; 4]);
+        assert_eq!(pairs, vec![(0, 3), (1, 2)]);
+        // deck 0 has met everyone, it meets the next deck again
+        let played = met(4, &[(0, 1), (0, 2), (0, 3)]);
+        let (pairs, _) = swiss_pairs(&[0, 1, 2, 3], &played, &[false; 4]);
+        assert_eq!(pairs, vec![(0, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn the_lowest_deck_without_a_bye_sits_out_a_round() {
+        let (pairs, bye) = swiss_pairs(&[0, 1, 2], &met(3, &[]), &[false, false, true]);
+        assert_eq!(bye, Some(1));
+        assert_eq!(pairs, vec![(0, 2)]);
+        // when all have had a bye, the lowest deck gets another one
+        let (pairs, bye) = swiss_pairs(&[0, 1, 2], &met(3, &[]), &[true; 3]);
+        assert_eq!(bye, Some(2));
+        assert_eq!(pairs, vec![(0, 1)]);
+    }
+
+    #[test]
+    fn ratings_move_towards_the_results() {
+        let (a, b) = elo_update(1500.0, 1500.0, 1.0);
+        assert_eq!((a, b), (1500.0 + ELO_K / 2.0, 1500.0 - ELO_K / 2.0));
+        assert_eq!(elo_update(1500.0, 1500.0, 0.5), (1500.0, 1500.0));
+        // an expected win gains less than an upset
+        let (favorite, _) = elo_update(1700.0, 1500.0, 1.0);
+        let (underdog, _) = elo_update(1500.0, 1700.0, 1.0);
+        assert!(favorite - 1700.0 < underdog - 1500.0);
+        // the points are only exchanged
+        let (a, b) = elo_update(1620.0, 1480.0, 0.0);
+        assert!((a + b - 3100.0).abs() < 1e-9);
+    }
+}
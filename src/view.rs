@@ -0,0 +1,646 @@
+use crate::card::{CardDefID, CardDefinition, CardRepository};
+use crate::filter::public_query;
+use crate::{
+    Card, CardID, Game, GameRng, HandleError, Message, MessageConsumer, Object, ObjectID,
+    ObjectKind, ObjectLocation, Player, PlayerID, PublicCardID, Query, Spell, Step, Substep, Team,
+    TeamID, UndoStack,
+};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A card as far as it is known to the viewer.
+/// Cards in hidden zones are placeholders without a definition.
+#[derive(Debug, Clone)]
+pub struct ViewCard {
+    pub id: PublicCardID,
+    pub owner_id: PlayerID,
+    pub def_id: Option<CardDefID>,
+}
+
+/// The game as seen by one player, built from the messages `filter::redact` lets through.
+/// All card ids are public ids. The players, teams and objects use the types of the game,
+/// with libraries and hands holding the public ids of placeholder cards where the
+/// viewer does not know them.
+#[derive(Debug, Clone)]
+pub struct PlayerView {
+    pub player_id: PlayerID,
+    pub cards: BTreeMap<PublicCardID, ViewCard>,
+    pub players: Vec<Player>,
+    pub teams: Vec<Team>,
+    pub objects: BTreeMap<ObjectID, Object>,
+    pub stack: Vec<Spell>,
+    pub step: Step,
+    pub substep: Substep,
+    pub active_player_id: PlayerID,
+    pub priority_player_id: PlayerID,
+    /// the query the viewer has to answer, if any
+    pub maybe_query: Option<Query>,
+    pub winning_team: Option<TeamID>,
+    /// the definitions of the cards the viewer started with,
+    /// the deck lists of the other players are not known
+    pub deck_list: Vec<CardDefID>,
+    card_repository: Arc<CardRepository>,
+    /// the view as it was before each accepted answer
+    undo: UndoStack<PlayerView>,
+}
+
+impl PlayerView {
+    pub fn new(player_id: PlayerID, card_repository: Arc<CardRepository>) -> Self {
+        PlayerView {
+            player_id,
+            cards: BTreeMap::new(),
+            players: Vec::new(),
+            teams: Vec::new(),
+            objects: BTreeMap::new(),
+            stack: Vec::new(),
+            step: Step::Untap,
+            substep: Substep::InitialShuffle,
+            active_player_id: 0,
+            priority_player_id: 0,
+            maybe_query: None,
+            winning_team: None,
+            deck_list: Vec::new(),
+            card_repository,
+            undo: UndoStack::default(),
+        }
+    }
+
+    /// The view `player_id` has of a game in progress, with the same public ids as the
+    /// stream of the game would have given. Only the answers from now on can be taken back.
+    pub fn from_game(game: &Game, player_id: PlayerID) -> Self {
+        let mut view = PlayerView::new(player_id, game.card_repository.clone());
+        let public =
+            |card_ids: &[CardID]| card_ids.iter().map(|cid| game.public_id(*cid)).collect();
+        for player in &game.players {
+            let mut player = player.clone();
+            player.library = public(&player.library);
+            player.hand = public(&player.hand);
+            player.graveyard = public(&player.graveyard);
+            player.removed = public(&player.removed);
+            view.players.push(player);
+        }
+        for card in game.cards.values() {
+            if card.owner_id == player_id {
+                view.deck_list.push(card.def_id);
+            }
+            let owner = &game.players[card.owner_id];
+            let hidden = owner.library.contains(&card.id)
+                || owner.removed.contains(&card.id)
+                || (card.owner_id != player_id && owner.hand.contains(&card.id));
+            let card = ViewCard {
+                id: card.public_id,
+                owner_id: card.owner_id,
+                def_id: if hidden { None } else { Some(card.def_id) },
+            };
+            view.cards.insert(card.id, card);
+        }
+        for object in game.objects.values() {
+            let mut object = object.clone();
+            object.kind = match object.kind {
+                ObjectKind::Card(cid) => ObjectKind::Card(game.public_id(cid)),
+                ObjectKind::ActivatedAbility(cid, n) => {
+                    ObjectKind::ActivatedAbility(game.public_id(cid), n)
+                }
+            };
+            view.objects.insert(object.id, object);
+        }
+        view.teams = game.teams.clone();
+        view.stack = game.stack.clone();
+        view.step = game.step;
+        view.substep = game.substep;
+        view.active_player_id = game.active_player_id;
+        view.priority_player_id = game.priority_player_id;
+        view.maybe_query = match &game.maybe_query {
+            Some((pid, query)) if *pid == player_id => Some(public_query(game, query)),
+            _ => None,
+        };
+        if game.substep == Substep::GameEnded {
+            view.winning_team = game.winning_team();
+        }
+        view
+    }
+
+    /// The team of the viewer
+    pub fn team_id(&self) -> TeamID {
+        self.players[self.player_id].team_id
+    }
+
+    /// The definition of a card, `None` for a placeholder
+    pub fn definition(&self, card_id: PublicCardID) -> Option<&CardDefinition> {
+        let def_id = self.cards.get(&card_id)?.def_id?;
+        self.card_repository.get(&def_id)
+    }
+
+    pub fn card_repository(&self) -> &CardRepository {
+        &self.card_repository
+    }
+
+    /// Name of a card definition for display
+    pub fn definition_name(&self, def_id: CardDefID) -> String {
+        match self.card_repository.get(&def_id) {
+            Some(def) => format!("{} ({})", def.display.name, def_id),
+            None => format!("unknown card ({})", def_id),
+        }
+    }
+
+    /// Name of a card for display, hidden cards are shown as such
+    pub fn card_name(&self, card_id: PublicCardID) -> String {
+        match self.definition(card_id) {
+            Some(def) => format!("{} [{}]", def.display.name, card_id),
+            None => format!("hidden card [{}]", card_id),
+        }
+    }
+
+    /// A complete game consistent with the view, in which all placeholders are given
+    /// definitions at random from what the viewer knows: the placeholders of the viewer
+    /// are drawn from what is left of their deck list, those of another player are
+    /// guessed among the definitions of the cards of that player seen so far, or among
+    /// all definitions if none has been seen.
+    /// The card ids of the game are the public ids of the view and its random number
+    /// generator is seeded from `rng`, so the returned game can be played on.
+    pub fn determinize(&self, rng: &mut GameRng) -> Game {
+        let mut game = Game::new(self.card_repository.clone());
+        game.rng = GameRng::seed_from_u64(rng.gen());
+        game.undo = UndoStack::new(0);
+
+        let mut unknown_defs = self.deck_list.clone();
+        let mut seen_defs = vec![Vec::new(); self.players.len()];
+        for card in self.cards.values() {
+            if let Some(def_id) = card.def_id {
+                seen_defs[card.owner_id].push(def_id);
+                if card.owner_id == self.player_id {
+                    if let Some(i) = unknown_defs.iter().position(|d| *d == def_id) {
+                        unknown_defs.swap_remove(i);
+                    }
+                }
+            }
+        }
+        unknown_defs.shuffle(rng);
+        let mut all_defs: Vec<CardDefID> = self.card_repository.keys().copied().collect();
+        all_defs.sort();
+
+        for card in self.cards.values() {
+            let def_id = match card.def_id {
+                Some(def_id) => def_id,
+                None if card.owner_id == self.player_id => unknown_defs
+                    .pop()
+                    .expect("more hidden cards than left in the deck list"),
+                None => {
+                    let seen = &seen_defs[card.owner_id];
+                    let guesses = if seen.is_empty() { &all_defs } else { seen };
+                    *guesses.choose(rng).expect("there are no card definitions")
+                }
+            };
+            let object_id = self.objects.values().find_map(|object| match object.kind {
+                ObjectKind::Card(cid) if cid == card.id => Some(object.id),
+                _ => None,
+            });
+            let card = Card {
+                id: card.id,
+                public_id: card.id,
+                previous_public_id: card.id,
+                object_id,
+                owner_id: card.owner_id,
+                def_id,
+            };
+            game.cards.insert(card.id, card);
+        }
+        game.next_public_id = self.cards.keys().last().map_or(1, |id| id + 1);
+        if let Some(object_id) = self.objects.keys().last() {
+            game.next_id = object_id + 1;
+        }
+
+        game.players = self.players.clone();
+        game.teams = self.teams.clone();
+        game.objects = self.objects.clone();
+        game.stack = self.stack.clone();
+        game.step = self.step;
+        game.substep = self.substep;
+        game.active_player_id = self.active_player_id;
+        game.priority_player_id = self.priority_player_id;
+        game.maybe_query = self
+            .maybe_query
+            .clone()
+            .map(|query| (self.player_id, query));
+        game
+    }
+
+    fn player_mut(&mut self, player_id: PlayerID) -> Result<&mut Player, HandleError> {
+        self.players
+            .get_mut(player_id)
+            .ok_or(HandleError::PlayerIdError(player_id))
+    }
+
+    /// Replace the cards in the library of `player_id` by placeholders with new ids
+    fn replace_library(&mut self, player_id: PlayerID, library: Vec<PublicCardID>) {
+        for card_id in &self.players[player_id].library {
+            self.cards.remove(card_id);
+        }
+        for card_id in &library {
+            self.add_placeholder(*card_id, player_id);
+        }
+        self.players[player_id].library = library;
+    }
+
+    fn add_placeholder(&mut self, card_id: PublicCardID, owner_id: PlayerID) {
+        let card = ViewCard {
+            id: card_id,
+            owner_id,
+            def_id: None,
+        };
+        self.cards.insert(card_id, card);
+    }
+}
+
+impl MessageConsumer for PlayerView {
+    fn handle_message(&mut self, message: &Message) -> Result<(), HandleError> {
+        self.apply(message)
+            .map_err(|e| HandleError::InvalidMessage(Box::new(message.clone()), Box::new(e)))
+    }
+}
+
+impl PlayerView {
+    fn apply(&mut self, message: &Message) -> Result<(), HandleError> {
+        match message {
+            Message::Query(pid, query) => {
+                self.player_mut(*pid)?;
+                if *pid == self.player_id {
+                    self.maybe_query = Some(query.clone());
+                }
+                Ok(())
+            }
+            Message::AcceptAnswer => {
+                if self.undo.is_enabled() {
+                    let undo = std::mem::take(&mut self.undo);
+                    let snapshot = self.clone();
+                    self.undo = undo;
+                    self.undo.push(snapshot);
+                }
+                self.maybe_query = None;
+                Ok(())
+            }
+            Message::RejectAnswer => {
+                self.maybe_query = None;
+                Ok(())
+            }
+            Message::AllowUndo(pid, _) => {
+                self.player_mut(*pid)?;
+                self.maybe_query = None;
+                Ok(())
+            }
+            Message::UndoLimit(limit) => {
+                self.undo.set_limit(*limit);
+                Ok(())
+            }
+            Message::Rollback(n) => {
+                let mut undo = std::mem::take(&mut self.undo);
+                match undo.pop(*n) {
+                    Some(snapshot) => {
+                        *self = snapshot;
+                        self.undo = undo;
+                        Ok(())
+                    }
+                    None => {
+                        self.undo = undo;
+                        Err(HandleError::RollbackError(*n))
+                    }
+                }
+            }
+            Message::CreateTeam { id, life } => {
+                if self.teams.len() != *id {
+                    Err(HandleError::TeamIdError(*id))
+                } else {
+                    self.teams.push(Team::new(*id, *life));
+                    Ok(())
+                }
+            }
+            Message::CreatePlayer { id, name, team_id } => {
+                if self.players.len() != *id {
+                    Err(HandleError::PlayerIdError(*id))
+                } else if let Some(team) = self.teams.get_mut(*team_id) {
+                    team.player_ids.push(*id);
+                    self.players.push(Player::new(*id, name.clone(), *team_id));
+                    Ok(())
+                } else {
+                    Err(HandleError::TeamIdError(*team_id))
+                }
+            }
+            Message::AddCard {
+                id,
+                owner_id,
+                def_id,
+            } => {
+                if !self.card_repository.contains_key(def_id) {
+                    return Err(HandleError::CardDefIdError(*def_id));
+                }
+                if self.cards.contains_key(id) {
+                    return Err(HandleError::CardIdError(*id));
+                }
+                self.player_mut(*owner_id)?.library.push(*id);
+                if *owner_id == self.player_id {
+                    self.deck_list.push(*def_id);
+                }
+                let card = ViewCard {
+                    id: *id,
+                    owner_id: *owner_id,
+                    def_id: Some(*def_id),
+                };
+                self.cards.insert(*id, card);
+                Ok(())
+            }
+            Message::AddHiddenCard { id, owner_id } => {
+                if self.cards.contains_key(id) {
+                    return Err(HandleError::CardIdError(*id));
+                }
+                self.player_mut(*owner_id)?.library.push(*id);
+                self.add_placeholder(*id, *owner_id);
+                Ok(())
+            }
+            Message::Substep(s) => {
+                self.substep = *s;
+                Ok(())
+            }
+            Message::Step(s) => {
+                self.step = *s;
+                Ok(())
+            }
+            Message::BeginTurn(pid) => {
+                let team_id = self.player_mut(*pid)?.team_id;
+                self.active_player_id = *pid;
+                for player_id in &self.teams[team_id].player_ids {
+                    self.players[*player_id].lands_played = 0;
+                }
+                Ok(())
+            }
+            Message::GetPriority(pid) | Message::PlayerHasPriority(pid) => {
+                self.player_mut(*pid)?;
+                self.priority_player_id = *pid;
+                Ok(())
+            }
+            Message::Seed(_) => {
+                // The seed is never shown to players
+                Ok(())
+            }
+            Message::ShuffleLibrary(pid, order) => {
+                if self.player_mut(*pid)?.library.len() != order.len() {
+                    return Err(HandleError::LibraryError(*pid));
+                }
+                if let Some(card_id) = order.iter().find(|cid| self.cards.contains_key(cid)) {
+                    return Err(HandleError::CardIdError(*card_id));
+                }
+                self.replace_library(*pid, order.clone());
+                Ok(())
+            }
+            Message::DrawCard(pid, cid) => match self.player_mut(*pid)?.library.pop() {
+                Some(old_id) => {
+                    self.cards.remove(&old_id);
+                    self.add_placeholder(*cid, *pid);
+                    self.players[*pid].hand.push(*cid);
+                    Ok(())
+                }
+                None => Err(HandleError::LibraryError(*pid)),
+            },
+            Message::NewPublicId(old_id, new_id) => {
+                if self.cards.contains_key(new_id) {
+                    return Err(HandleError::CardIdError(*new_id));
+                }
+                let mut card = self
+                    .cards
+                    .remove(old_id)
+                    .ok_or(HandleError::CardIdError(*old_id))?;
+                card.id = *new_id;
+                self.cards.insert(*new_id, card);
+                let rename = |cid: &mut PublicCardID| {
+                    if cid == old_id {
+                        *cid = *new_id;
+                    }
+                };
+                for player in &mut self.players {
+                    let zones = [
+                        &mut player.library,
+                        &mut player.hand,
+                        &mut player.graveyard,
+                        &mut player.removed,
+                    ];
+                    zones.into_iter().flatten().for_each(rename);
+                }
+                for object in self.objects.values_mut() {
+                    match &mut object.kind {
+                        ObjectKind::Card(cid) | ObjectKind::ActivatedAbility(cid, _) => rename(cid),
+                    }
+                }
+                Ok(())
+            }
+            Message::Reveal(cid, def_id) => match self.cards.get_mut(cid) {
+                Some(card) => {
+                    card.def_id = Some(*def_id);
+                    Ok(())
+                }
+                None => Err(HandleError::CardIdError(*cid)),
+            },
+            Message::DrawFromEmpty(pid) => {
+                self.player_mut(*pid)?.has_drawn_from_empty = true;
+                Ok(())
+            }
+            Message::PlayerLoses(pid, _) => {
+                self.player_mut(*pid)?.has_lost = true;
+                Ok(())
+            }
+            Message::TeamWins(tid) => {
+                if *tid >= self.teams.len() {
+                    return Err(HandleError::TeamIdError(*tid));
+                }
+                self.winning_team = Some(*tid);
+                Ok(())
+            }
+            Message::PlayerPasses(pid) => {
+                self.player_mut(*pid)?.has_passed = true;
+                Ok(())
+            }
+            Message::PriorityEnded => {
+                for p in self.players.iter_mut() {
+                    p.has_passed = false;
+                }
+                Ok(())
+            }
+            Message::ResolveSpell(sid) => match self.stack.last() {
+                Some(spell) if spell.id == *sid => {
+                    self.stack.pop();
+                    Ok(())
+                }
+                _ => Err(HandleError::SpellIdError(*sid)),
+            },
+            Message::Discard(pid, cid) => {
+                let player = self.player_mut(*pid)?;
+                match player.hand.iter().position(|c| *c == *cid) {
+                    Some(i) => {
+                        let card_id = player.hand.remove(i);
+                        player.graveyard.push(card_id);
+                        Ok(())
+                    }
+                    None => Err(HandleError::CardIdError(*cid)),
+                }
+            }
+            Message::PlayLand(pid, cid, oid) => {
+                if self.objects.contains_key(oid) {
+                    return Err(HandleError::ObjectIdError(*oid));
+                }
+                let player = self.player_mut(*pid)?;
+                match player.hand.iter().position(|c| *c == *cid) {
+                    Some(i) => {
+                        let card_id = player.hand.remove(i);
+                        player.lands_played += 1;
+                        let object = Object {
+                            id: *oid,
+                            controller_id: *pid,
+                            kind: ObjectKind::Card(card_id),
+                            location: ObjectLocation::Battlefield,
+                        };
+                        self.objects.insert(*oid, object);
+                        Ok(())
+                    }
+                    None => Err(HandleError::CardIdError(*cid)),
+                }
+            }
+            Message::RemoveObject(oid) => match self.objects.remove(oid) {
+                Some(_) => Ok(()),
+                None => Err(HandleError::ObjectIdError(*oid)),
+            },
+            Message::RemoveFromGame(cid) => {
+                // the removed cards are not looked at again, they become placeholders
+                let card = self
+                    .cards
+                    .get_mut(cid)
+                    .ok_or(HandleError::CardIdError(*cid))?;
+                card.def_id = None;
+                let owner_id = card.owner_id;
+                let object_id = self.objects.values().find_map(|object| match object.kind {
+                    ObjectKind::Card(c) if c == *cid => Some(object.id),
+                    _ => None,
+                });
+                if let Some(oid) = object_id {
+                    self.objects.remove(&oid);
+                } else {
+                    let player = self.player_mut(owner_id)?;
+                    let zone = [&mut player.library, &mut player.hand, &mut player.graveyard]
+                        .into_iter()
+                        .find(|zone| zone.contains(cid))
+                        .ok_or(HandleError::CardIdError(*cid))?;
+                    zone.retain(|c| c != cid);
+                }
+                self.player_mut(owner_id)?.removed.push(*cid);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{PlayerAgent, RandomAgent};
+    use crate::card::{load_cards, Deck};
+    use crate::filter::redact;
+    use crate::{duel, Answer, PriorityAction, User};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Collector(Rc<RefCell<Vec<Message>>>);
+
+    impl MessageConsumer for Collector {
+        fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+            self.0.borrow_mut().push(msg.clone());
+            Ok(())
+        }
+    }
+
+    /// Never plays a land, so that it has to discard
+    struct Passer();
+
+    impl PlayerAgent for Passer {
+        fn answer(&mut self, _view: &PlayerView, query: &Query) -> Answer {
+            match query {
+                Query::Discard(cards, n) => Answer::Discard(cards[..*n as usize].to_vec()),
+                _ => Answer::PriorityAction(PriorityAction::Pass),
+            }
+        }
+    }
+
+    /// The parts of a view that the stream and the game must agree on
+    fn state(view: &PlayerView) -> String {
+        format!(
+            "{:?}",
+            (
+                &view.cards,
+                &view.players,
+                &view.teams,
+                &view.objects,
+                &view.stack,
+                (view.step, view.substep),
+                (view.active_player_id, view.priority_player_id),
+                &view.maybe_query,
+                view.winning_team,
+                &view.deck_list,
+            )
+        )
+    }
+
+    #[test]
+    fn a_view_built_from_the_stream_is_the_view_of_the_game() {
+        let card_repository = Arc::new(load_cards());
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let mut consumers: Vec<Box<dyn MessageConsumer>> =
+            vec![Box::new(Collector(messages.clone()))];
+        let mut agents: Vec<Box<dyn PlayerAgent>> =
+            vec![Box::new(RandomAgent::new(1)), Box::new(Passer())];
+        let user = |name: &str| User {
+            name: name.to_string(),
+        };
+        duel(
+            user("Leo"),
+            Deck(vec![(101, 12)]),
+            user("Marc"),
+            Deck(vec![(101, 12)]),
+            &mut agents,
+            &mut consumers,
+            card_repository.clone(),
+            3,
+        )
+        .unwrap();
+
+        // cards leave the hands of the players in both ways
+        let messages = messages.borrow();
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::Discard(..))));
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::PlayLand(..))));
+
+        let mut game = Game::new(card_repository.clone());
+        let mut views: Vec<_> = (0..2)
+            .map(|pid| PlayerView::new(pid, card_repository.clone()))
+            .collect();
+        for (i, msg) in messages.iter().enumerate() {
+            game.handle_message(msg).unwrap();
+            for view in views.iter_mut() {
+                for msg in redact(&game, view.player_id, msg) {
+                    view.handle_message(&msg).unwrap();
+                }
+            }
+            // once the players are seated
+            if i % 25 == 0 && game.substep != Substep::InitialShuffle {
+                for view in &views {
+                    let expected = PlayerView::from_game(&game, view.player_id);
+                    assert_eq!(state(view), state(&expected), "after message {}", i);
+                }
+            }
+        }
+        for view in &views {
+            let expected = PlayerView::from_game(&game, view.player_id);
+            assert_eq!(state(view), state(&expected));
+        }
+    }
+}
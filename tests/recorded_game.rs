@@ -0,0 +1,71 @@
+use bote::agent::{PlayerAgent, RandomAgent, RecordingAgent, ScriptedAgent};
+use bote::card::{load_cards, Deck};
+use bote::{duel, HandleError, Message, MessageConsumer, User};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Collects the messages of a game as JSON
+struct Collector(Rc<RefCell<Vec<String>>>);
+
+impl MessageConsumer for Collector {
+    fn handle_message(&mut self, msg: &Message) -> Result<(), HandleError> {
+        self.0
+            .borrow_mut()
+            .push(serde_json::to_string(msg).unwrap());
+        Ok(())
+    }
+}
+
+/// Play a duel with the given agents and return its messages
+fn play(agents: &mut [Box<dyn PlayerAgent>], seed: u64) -> Vec<String> {
+    let messages = Rc::new(RefCell::new(Vec::new()));
+    let mut consumers: Vec<Box<dyn MessageConsumer>> = vec![Box::new(Collector(messages.clone()))];
+    let user = |name: &str| User {
+        name: name.to_string(),
+    };
+    duel(
+        user("Leo"),
+        Deck(vec![(101, 12)]),
+        user("Marc"),
+        Deck(vec![(101, 12)]),
+        agents,
+        &mut consumers,
+        Arc::new(load_cards()),
+        seed,
+    )
+    .unwrap();
+    drop(consumers);
+    Rc::try_unwrap(messages).unwrap().into_inner()
+}
+
+#[test]
+fn a_recorded_game_is_played_again_exactly() {
+    let seed = 7;
+    let path = |player_id: usize| -> PathBuf {
+        let name = format!("bote-recorded-{}.{}", std::process::id(), player_id);
+        std::env::temp_dir().join(name)
+    };
+    let mut agents: Vec<Box<dyn PlayerAgent>> = (0..2)
+        .map(|player_id| {
+            let agent = Box::new(RandomAgent::new(seed + player_id as u64));
+            Box::new(RecordingAgent::create(path(player_id), agent).unwrap())
+                as Box<dyn PlayerAgent>
+        })
+        .collect();
+    let recorded = play(&mut agents, seed);
+    drop(agents);
+
+    let mut agents: Vec<Box<dyn PlayerAgent>> = (0..2)
+        .map(|player_id| {
+            Box::new(ScriptedAgent::read(path(player_id)).unwrap()) as Box<dyn PlayerAgent>
+        })
+        .collect();
+    let replayed = play(&mut agents, seed);
+    for player_id in 0..2 {
+        std::fs::remove_file(path(player_id)).unwrap();
+    }
+    assert!(recorded.iter().any(|msg| msg.contains("PlayLand")));
+    assert_eq!(recorded, replayed);
+}
@@ -0,0 +1,85 @@
+use bote::agent::HeuristicAgent;
+use bote::card::{load_cards, Deck};
+use bote::client::Client;
+use bote::server::{serve, ClientMessage, ServerMessage};
+use bote::{Message, User};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn user(name: &str) -> User {
+    User {
+        name: name.to_string(),
+    }
+}
+
+/// Join the game as `name` and play it to the end with the heuristic agent.
+/// Returns the winning team as seen by the client.
+fn play(address: &str, name: &str) -> Option<usize> {
+    let mut client = Client::connect(address, user(name), Arc::new(load_cards())).unwrap();
+    client.play(&mut HeuristicAgent()).unwrap();
+    client.view().winning_team
+}
+
+#[test]
+fn a_player_rejoins_a_game_in_progress() {
+    let start = Instant::now();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let players = vec![
+        (user("Leo"), Deck(vec![(101, 12)])),
+        (user("Marc"), Deck(vec![(101, 12)])),
+    ];
+    let server = thread::spawn(move || {
+        serve(
+            listener,
+            players,
+            Vec::new(),
+            Arc::new(load_cards()),
+            3,
+            false,
+        )
+    });
+
+    // a connection which never says hello does not keep the players from joining
+    let silent = TcpStream::connect(&address).unwrap();
+    let leo = {
+        let address = address.clone();
+        thread::spawn(move || play(&address, "Leo"))
+    };
+
+    // Marc leaves the game at their first query, without answering it
+    let mut stream = TcpStream::connect(&address).unwrap();
+    let hello = serde_json::to_string(&ClientMessage::Hello(user("Marc"))).unwrap();
+    writeln!(stream, "{}", hello).unwrap();
+    let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+    let welcome: ServerMessage = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert!(matches!(
+        welcome,
+        ServerMessage::Welcome {
+            player_id: 1,
+            resync: 0
+        }
+    ));
+    for line in lines.by_ref() {
+        let msg: ServerMessage = serde_json::from_str(&line.unwrap()).unwrap();
+        if let ServerMessage::Message(Message::Query(1, _)) = msg {
+            break;
+        }
+    }
+    drop(lines);
+    drop(stream);
+
+    // and comes back to finish it
+    let marc = play(&address, "Marc");
+    let leo = leo.join().unwrap();
+    let game = server.join().unwrap().unwrap();
+    drop(silent);
+    assert!(game.winning_team().is_some());
+    assert_eq!(leo, game.winning_team());
+    assert_eq!(marc, game.winning_team());
+    // the silent connection is only given up after the handshake timeout of 10 seconds
+    assert!(start.elapsed() < Duration::from_secs(10));
+}